@@ -0,0 +1,202 @@
+use crate::error::WaterError;
+use anyhow::{bail, ensure, Error, Result};
+
+/// Graphics API to look for adapters on
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum Backend {
+    /// Whichever APIs the platform supports
+    #[default]
+    All,
+    Vulkan,
+    Gl,
+    Metal,
+    Dx12,
+    Dx11,
+}
+
+impl std::str::FromStr for Backend {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_lowercase().as_str() {
+            "all" => Ok(Backend::All),
+            "vulkan" => Ok(Backend::Vulkan),
+            "gl" => Ok(Backend::Gl),
+            "metal" => Ok(Backend::Metal),
+            "dx12" => Ok(Backend::Dx12),
+            "dx11" => Ok(Backend::Dx11),
+            _ => bail!(
+                "Unknown backend {:?}, expected all, vulkan, gl, metal, dx12 or dx11",
+                s
+            ),
+        }
+    }
+}
+
+impl Backend {
+    pub fn backends(self) -> wgpu::Backends {
+        match self {
+            Backend::All => wgpu::Backends::all(),
+            Backend::Vulkan => wgpu::Backends::VULKAN,
+            Backend::Gl => wgpu::Backends::GL,
+            Backend::Metal => wgpu::Backends::METAL,
+            Backend::Dx12 => wgpu::Backends::DX12,
+            Backend::Dx11 => wgpu::Backends::DX11,
+        }
+    }
+}
+
+/// A particular adapter asked for by the user
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum AdapterChoice {
+    /// Position in the list printed by [`list_adapters`]
+    Index(usize),
+    /// Part of the adapter name, ignoring case
+    Name(String),
+}
+
+impl std::str::FromStr for AdapterChoice {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        ensure!(!s.is_empty(), "Adapter name must not be empty");
+        Ok(match s.parse() {
+            Ok(index) => AdapterChoice::Index(index),
+            Err(_) => AdapterChoice::Name(s.to_owned()),
+        })
+    }
+}
+
+impl std::fmt::Display for AdapterChoice {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            AdapterChoice::Index(index) => write!(f, "index {}", index),
+            AdapterChoice::Name(name) => write!(f, "{:?}", name),
+        }
+    }
+}
+
+/// Which graphics adapter to render with
+///
+/// By default the best hardware adapter is used, falling back to a software adapter such as
+/// llvmpipe when there is none.
+#[derive(Clone, Debug, Default)]
+pub struct AdapterOptions {
+    pub backend: Backend,
+    /// Use this adapter instead of picking one. There is no fallback if it can't be used
+    pub choice: Option<AdapterChoice>,
+    /// Skip hardware adapters and go straight to the software one, so the output doesn't depend
+    /// on the gpu
+    pub software: bool,
+}
+
+impl AdapterOptions {
+    /// Finds the adapter to use, checking it can present to `surface` if one is given
+    ///
+    /// # Example
+    /// ```ignore
+    /// let instance = wgpu::Instance::new(options.backend.backends());
+    /// let adapter = options.select(&instance, Some(&surface)).await?;
+    /// ```
+    pub async fn select(
+        &self,
+        instance: &wgpu::Instance,
+        surface: Option<&wgpu::Surface>,
+    ) -> Result<wgpu::Adapter, WaterError> {
+        let supports_surface =
+            |adapter: &wgpu::Adapter| surface.is_none_or(|s| adapter.is_surface_supported(s));
+
+        if let Some(choice) = &self.choice {
+            let adapters = instance.enumerate_adapters(self.backend.backends());
+            let adapter = match choice {
+                AdapterChoice::Index(index) => adapters.enumerate().find(|(i, _)| i == index),
+                AdapterChoice::Name(name) => {
+                    let name = name.to_lowercase();
+                    adapters
+                        .enumerate()
+                        .find(|(_, a)| a.get_info().name.to_lowercase().contains(&name))
+                }
+            };
+            let (_, adapter) = adapter.ok_or_else(|| WaterError::UnknownAdapter {
+                choice: choice.clone(),
+            })?;
+            if !supports_surface(&adapter) {
+                return Err(WaterError::Adapter {
+                    adapter: adapter.get_info().name,
+                });
+            }
+            return Ok(adapter);
+        }
+
+        let fallback: &[bool] = if self.software {
+            &[true]
+        } else {
+            &[false, true]
+        };
+        for &force_fallback_adapter in fallback {
+            let adapter = instance
+                .request_adapter(&wgpu::RequestAdapterOptions {
+                    // Prefers dedicated gpu over cpu based gpu
+                    power_preference: wgpu::PowerPreference::HighPerformance,
+                    force_fallback_adapter,
+                    compatible_surface: surface,
+                })
+                .await;
+            if let Some(adapter) = adapter {
+                return Ok(adapter);
+            }
+        }
+        Err(WaterError::NoAdapter {
+            backend: self.backend,
+        })
+    }
+}
+
+/// Whether `adapter` renders on the cpu, like llvmpipe or WARP, so heavy work should be scaled down
+pub(crate) fn is_software(adapter: &wgpu::Adapter) -> bool {
+    adapter.get_info().device_type == wgpu::DeviceType::Cpu
+}
+
+/// Opens a device on `adapter` with limits every adapter, including software ones, can meet
+pub(crate) async fn request_device(
+    adapter: &wgpu::Adapter,
+) -> Result<(wgpu::Device, wgpu::Queue), WaterError> {
+    adapter
+        .request_device(
+            &wgpu::DeviceDescriptor {
+                features: wgpu::Features::empty(),
+                // Software adapters rarely meet the default limits
+                limits: wgpu::Limits::downlevel_defaults().using_resolution(adapter.limits()),
+                label: None,
+            },
+            None,
+        )
+        .await
+        .map_err(|source| WaterError::Device {
+            adapter: adapter.get_info().name,
+            source,
+        })
+}
+
+/// Describes every adapter on `backend`, numbered the same way as [`AdapterChoice::Index`]
+///
+/// # Example
+/// ```ignore
+/// for line in list_adapters(Backend::All) {
+///     println!("{}", line);
+/// }
+/// ```
+pub fn list_adapters(backend: Backend) -> Vec<String> {
+    let instance = wgpu::Instance::new(backend.backends());
+    instance
+        .enumerate_adapters(backend.backends())
+        .enumerate()
+        .map(|(index, adapter)| {
+            let info = adapter.get_info();
+            format!(
+                "{}: {} ({:?}, {:?})",
+                index, info.name, info.backend, info.device_type
+            )
+        })
+        .collect()
+}
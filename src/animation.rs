@@ -0,0 +1,299 @@
+use anyhow::*;
+use image::codecs::{gif::GifDecoder, png::PngDecoder};
+use image::AnimationDecoder;
+use std::fs::File;
+use std::io::BufReader;
+use std::path::{Path, PathBuf};
+
+// Browsers treat gif frames without a delay as 100ms, so match them
+const DEFAULT_DELAY: f32 = 0.1;
+
+/// How an animation continues once it reaches its last frame
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum Playback {
+    /// Starts again from the first frame
+    #[default]
+    Loop,
+    /// Plays backwards to the first frame, then forwards again
+    PingPong,
+}
+
+impl std::str::FromStr for Playback {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "loop" => Ok(Playback::Loop),
+            "ping-pong" => Ok(Playback::PingPong),
+            _ => bail!("Unknown playback mode {:?}, expected loop or ping-pong", s),
+        }
+    }
+}
+
+/// A single frame of an animation and how long it stays on screen in seconds
+pub struct Frame {
+    pub image: image::DynamicImage,
+    pub delay: f32,
+}
+
+/// A background image, which may be animated
+pub struct Animation {
+    pub frames: Vec<Frame>,
+    pub playback: Playback,
+}
+
+impl Animation {
+    /// Wraps a single image as an animation that never changes
+    pub fn still(image: image::DynamicImage) -> Self {
+        Animation {
+            frames: vec![Frame {
+                image,
+                delay: DEFAULT_DELAY,
+            }],
+            playback: Playback::Loop,
+        }
+    }
+
+    /// Loads an animation from a directory of numbered frames, an animated GIF or PNG, or a still
+    /// image
+    ///
+    /// Frames from a directory are shown for `1 / sequence_fps` seconds each, animated files use
+    /// their own frame delays.
+    ///
+    /// # Example
+    /// ```ignore
+    /// let animation = Animation::load(Path::new("flag.gif"), 12.0)?;
+    /// ```
+    pub fn load(path: &Path, sequence_fps: f32) -> Result<Self> {
+        let frames = if path.is_dir() {
+            ensure!(sequence_fps > 0.0, "Sequence frame rate must be positive");
+            load_sequence(path, 1.0 / sequence_fps)
+        } else {
+            load_file(path)
+        }
+        .with_context(|| format!("Failed to load {}", path.display()))?;
+
+        ensure!(!frames.is_empty(), "{} contains no frames", path.display());
+        Ok(Animation {
+            frames,
+            playback: Playback::Loop,
+        })
+    }
+
+    /// Timing information for the frames, without the images themselves
+    pub fn timeline(&self) -> Timeline {
+        let count = self.frames.len();
+        // Ping pong plays every frame but the two ends a second time, in reverse
+        let order: Vec<usize> = match self.playback {
+            Playback::PingPong if count > 2 => (0..count).chain((1..count - 1).rev()).collect(),
+            _ => (0..count).collect(),
+        };
+        Timeline {
+            period: order.iter().map(|&i| self.frames[i].delay).sum(),
+            delays: self.frames.iter().map(|frame| frame.delay).collect(),
+            order,
+        }
+    }
+}
+
+/// Decides which frame of an animation is shown at a point in time
+#[derive(Clone, Debug)]
+pub struct Timeline {
+    delays: Vec<f32>,
+    // Frames in the order they are shown over one period
+    order: Vec<usize>,
+    period: f32,
+}
+
+impl Timeline {
+    /// Returns the index of the frame shown `seconds` into the animation
+    pub fn frame_at(&self, seconds: f32) -> usize {
+        if self.order.len() < 2 || self.period <= 0.0 {
+            return 0;
+        }
+
+        let mut t = seconds.rem_euclid(self.period);
+        for &i in &self.order {
+            if t < self.delays[i] {
+                return i;
+            }
+            t -= self.delays[i];
+        }
+        // Only reachable through rounding at the very end of the period
+        self.order[self.order.len() - 1]
+    }
+}
+
+/// Where the background comes from and how it is played back
+#[derive(Clone, Debug)]
+pub struct BackgroundSource {
+    /// Image, animated image or directory of frames. Uses the embedded image when `None`
+    pub path: Option<PathBuf>,
+    pub playback: Playback,
+    /// Frame rate used for directories of frames
+    pub sequence_fps: f32,
+}
+
+impl Default for BackgroundSource {
+    fn default() -> Self {
+        BackgroundSource {
+            path: None,
+            playback: Playback::Loop,
+            sequence_fps: 12.0,
+        }
+    }
+}
+
+impl BackgroundSource {
+    /// Loads the background, falling back to the embedded image when enabled
+    pub fn load(&self) -> Result<Animation> {
+        let mut animation = match &self.path {
+            Some(path) => Animation::load(path, self.sequence_fps)?,
+            None => Animation::still(crate::embedded_background()?),
+        };
+        animation.playback = self.playback;
+        Ok(animation)
+    }
+}
+
+fn load_file(path: &Path) -> Result<Vec<Frame>> {
+    let extension = path
+        .extension()
+        .and_then(|extension| extension.to_str())
+        .map(str::to_ascii_lowercase);
+    let reader = || -> Result<BufReader<File>> { Ok(BufReader::new(File::open(path)?)) };
+
+    let frames = match extension.as_deref() {
+        Some("gif") => GifDecoder::new(reader()?)?.into_frames().collect_frames()?,
+        Some("png" | "apng") => {
+            let decoder = PngDecoder::new(reader()?)?;
+            if !decoder.is_apng() {
+                return Ok(vec![still_frame(path)?]);
+            }
+            decoder.apng().into_frames().collect_frames()?
+        }
+        _ => return Ok(vec![still_frame(path)?]),
+    };
+
+    Ok(frames
+        .into_iter()
+        .map(|frame| {
+            let (numer, denom) = frame.delay().numer_denom_ms();
+            let delay = numer as f32 / denom.max(1) as f32 / 1000.0;
+            Frame {
+                delay: if delay > 0.0 { delay } else { DEFAULT_DELAY },
+                image: image::DynamicImage::ImageRgba8(frame.into_buffer()),
+            }
+        })
+        .collect())
+}
+
+fn still_frame(path: &Path) -> Result<Frame> {
+    Ok(Frame {
+        image: image::open(path)?,
+        delay: DEFAULT_DELAY,
+    })
+}
+
+fn load_sequence(dir: &Path, delay: f32) -> Result<Vec<Frame>> {
+    let mut paths = Vec::new();
+    for entry in std::fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.is_file() && image::ImageFormat::from_path(&path).is_ok() {
+            paths.push(path);
+        }
+    }
+    // Sort by the number in the name so frame_10 comes after frame_9
+    paths.sort_by_cached_key(|path| (frame_number(path), path.clone()));
+
+    paths
+        .iter()
+        .map(|path| {
+            Ok(Frame {
+                image: image::open(path)
+                    .with_context(|| format!("Failed to load {}", path.display()))?,
+                delay,
+            })
+        })
+        .collect()
+}
+
+fn frame_number(path: &Path) -> Option<u64> {
+    let stem = path.file_stem()?.to_str()?;
+    let digits: String = stem
+        .chars()
+        .rev()
+        .skip_while(|c| !c.is_ascii_digit())
+        .take_while(|c| c.is_ascii_digit())
+        .collect();
+    digits.chars().rev().collect::<String>().parse().ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn animation(delays: &[f32], playback: Playback) -> Animation {
+        Animation {
+            frames: delays
+                .iter()
+                .map(|&delay| Frame {
+                    image: image::DynamicImage::new_rgba8(1, 1),
+                    delay,
+                })
+                .collect(),
+            playback,
+        }
+    }
+
+    fn frames(timeline: &Timeline, times: &[f32]) -> Vec<usize> {
+        times.iter().map(|&t| timeline.frame_at(t)).collect()
+    }
+
+    #[test]
+    fn loop_starts_again() {
+        let timeline = animation(&[0.1, 0.2, 0.1], Playback::Loop).timeline();
+        assert_eq!(
+            frames(&timeline, &[0.0, 0.05, 0.15, 0.35, 0.45, -0.05]),
+            [0, 0, 1, 2, 0, 2]
+        );
+    }
+
+    #[test]
+    fn ping_pong_plays_back_without_repeating_the_ends() {
+        let timeline = animation(&[0.1; 4], Playback::PingPong).timeline();
+        let times: Vec<f32> = (0..8).map(|i| i as f32 * 0.1 + 0.05).collect();
+        assert_eq!(frames(&timeline, &times), [0, 1, 2, 3, 2, 1, 0, 1]);
+
+        // Two frames have nothing in between to play backwards
+        let timeline = animation(&[0.1; 2], Playback::PingPong).timeline();
+        assert_eq!(frames(&timeline, &[0.05, 0.15, 0.25]), [0, 1, 0]);
+    }
+
+    #[test]
+    fn zero_delays_show_the_first_frame() {
+        let timeline = animation(&[0.0, 0.0], Playback::Loop).timeline();
+        assert_eq!(frames(&timeline, &[0.0, 1.0]), [0, 0]);
+
+        // Frames without a delay are skipped
+        let timeline = animation(&[0.1, 0.0, 0.1], Playback::Loop).timeline();
+        assert_eq!(frames(&timeline, &[0.05, 0.15]), [0, 2]);
+    }
+
+    #[test]
+    fn frame_numbers_come_from_the_end_of_the_name() {
+        assert_eq!(frame_number(Path::new("dir/frame_10.png")), Some(10));
+        assert_eq!(frame_number(Path::new("take2_frame_007.png")), Some(7));
+        assert_eq!(frame_number(Path::new("frame9b.png")), Some(9));
+        assert_eq!(frame_number(Path::new("cover.png")), None);
+
+        let mut paths: Vec<PathBuf> = ["frame_10.png", "frame_9.png", "frame_1.png"]
+            .map(PathBuf::from)
+            .into();
+        paths.sort_by_cached_key(|path| (frame_number(path), path.clone()));
+        assert_eq!(
+            paths,
+            ["frame_1.png", "frame_9.png", "frame_10.png"].map(PathBuf::from)
+        );
+    }
+}
@@ -0,0 +1,122 @@
+use std::time::Instant;
+
+/// Source of animation time, in seconds
+///
+/// Rendering the same time always produces the same frame, so the fixed step and manual clocks
+/// give reproducible output for tests and offline renders.
+#[derive(Clone, Debug)]
+pub enum Clock {
+    /// Follows the wall clock from when the clock was created
+    RealTime { start: Instant },
+    /// Advances by `step` seconds every tick, however long a frame takes
+    FixedStep { step: f32, frame: u64 },
+    /// Only changes when set
+    Manual { seconds: f32 },
+}
+
+impl Default for Clock {
+    fn default() -> Self {
+        Clock::real_time()
+    }
+}
+
+impl Clock {
+    pub fn real_time() -> Self {
+        Clock::RealTime {
+            start: Instant::now(),
+        }
+    }
+
+    pub fn fixed_step(step: f32) -> Self {
+        Clock::FixedStep { step, frame: 0 }
+    }
+
+    pub fn manual(seconds: f32) -> Self {
+        Clock::Manual { seconds }
+    }
+
+    /// Current animation time
+    ///
+    /// # Example
+    /// ```ignore
+    /// water.encode(&device, &queue, &mut encoder, &view, clock.seconds());
+    /// ```
+    pub fn seconds(&self) -> f32 {
+        match self {
+            // Instant is monotonic, so unlike SystemTime this can't fail when the clock is changed
+            Clock::RealTime { start } => start.elapsed().as_secs_f32(),
+            Clock::FixedStep { step, frame } => *frame as f32 * step,
+            Clock::Manual { seconds } => *seconds,
+        }
+    }
+
+    /// Moves on to the next frame. Only the fixed step clock is affected
+    pub fn tick(&mut self) {
+        if let Clock::FixedStep { frame, .. } = self {
+            *frame += 1;
+        }
+    }
+
+    /// Jumps to `seconds`
+    ///
+    /// Fixed step clocks jump to the closest frame.
+    pub fn set(&mut self, seconds: f32) {
+        match self {
+            Clock::RealTime { start } => {
+                let elapsed = std::time::Duration::from_secs_f32(seconds.max(0.0));
+                *start = Instant::now()
+                    .checked_sub(elapsed)
+                    .unwrap_or_else(Instant::now);
+            }
+            Clock::FixedStep { step, frame } => {
+                *frame = (seconds / *step).round().max(0.0) as u64;
+            }
+            Clock::Manual { seconds: current } => *current = seconds,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fixed_step_counts_ticks() {
+        let mut clock = Clock::fixed_step(0.25);
+        assert_eq!(clock.seconds(), 0.0);
+        clock.tick();
+        clock.tick();
+        assert_eq!(clock.seconds(), 0.5);
+    }
+
+    #[test]
+    fn fixed_step_jumps_to_the_closest_frame() {
+        let mut clock = Clock::fixed_step(0.25);
+        clock.set(1.1);
+        assert_eq!(clock.seconds(), 1.0);
+        clock.set(1.2);
+        assert_eq!(clock.seconds(), 1.25);
+
+        // There are no frames before the start
+        clock.set(-3.0);
+        assert_eq!(clock.seconds(), 0.0);
+        clock.tick();
+        assert_eq!(clock.seconds(), 0.25);
+    }
+
+    #[test]
+    fn manual_only_changes_when_set() {
+        let mut clock = Clock::manual(2.0);
+        clock.tick();
+        assert_eq!(clock.seconds(), 2.0);
+        clock.set(-1.5);
+        assert_eq!(clock.seconds(), -1.5);
+    }
+
+    #[test]
+    fn real_time_can_be_set() {
+        let mut clock = Clock::real_time();
+        clock.set(10.0);
+        assert!((10.0..11.0).contains(&clock.seconds()));
+    }
+}
@@ -0,0 +1,214 @@
+use crate::animation::{Animation, Timeline};
+use crate::noise;
+use crate::params::WaterParams;
+use anyhow::*;
+use image::{Rgba, RgbaImage};
+use rayon::prelude::*;
+
+// How far the background reaches below the horizon, as a fraction of the screen. Matches the
+// overlap in Background::vertices
+const BACKGROUND_OVERLAP: f32 = 0.0025;
+
+/// A decoded background frame, in linear colour like an sRGB texture on the gpu
+struct Image {
+    width: u32,
+    height: u32,
+    pixels: Vec<[f32; 4]>,
+}
+
+impl Image {
+    fn new(image: &image::DynamicImage) -> Self {
+        let rgba = image.to_rgba8();
+        Image {
+            width: rgba.width(),
+            height: rgba.height(),
+            pixels: rgba.pixels().map(to_linear).collect(),
+        }
+    }
+
+    fn texel(&self, x: i64, y: i64) -> [f32; 4] {
+        // Clamp to edge addressing
+        let x = x.clamp(0, self.width as i64 - 1) as usize;
+        let y = y.clamp(0, self.height as i64 - 1) as usize;
+        self.pixels[y * self.width as usize + x]
+    }
+
+    /// Samples like the texture samplers in defs: linear filtering when magnified, nearest when
+    /// minified
+    fn sample(&self, [u, v]: [f32; 2], linear: bool) -> [f32; 4] {
+        let x = u * self.width as f32;
+        let y = v * self.height as f32;
+        if !linear {
+            return self.texel(x.floor() as i64, y.floor() as i64);
+        }
+
+        let (x, y) = (x - 0.5, y - 0.5);
+        let (x0, y0) = (x.floor(), y.floor());
+        let (tx, ty) = (x - x0, y - y0);
+        let (x0, y0) = (x0 as i64, y0 as i64);
+        let top = lerp(self.texel(x0, y0), self.texel(x0 + 1, y0), tx);
+        let bottom = lerp(self.texel(x0, y0 + 1), self.texel(x0 + 1, y0 + 1), tx);
+        lerp(top, bottom, ty)
+    }
+}
+
+fn lerp(a: [f32; 4], b: [f32; 4], t: f32) -> [f32; 4] {
+    [0, 1, 2, 3].map(|i| a[i] + (b[i] - a[i]) * t)
+}
+
+fn to_linear(pixel: &Rgba<u8>) -> [f32; 4] {
+    let [r, g, b, a] = pixel.0.map(|c| c as f32 / 255.0);
+    let decode = |c: f32| {
+        if c <= 0.04045 {
+            c / 12.92
+        } else {
+            ((c + 0.055) / 1.055).powf(2.4)
+        }
+    };
+    [decode(r), decode(g), decode(b), a]
+}
+
+fn to_srgb(color: [f32; 4]) -> [u8; 4] {
+    let encode = |c: f32| {
+        if c <= 0.0031308 {
+            c * 12.92
+        } else {
+            1.055 * c.powf(1.0 / 2.4) - 0.055
+        }
+    };
+    let [r, g, b, a] = color;
+    [encode(r), encode(g), encode(b), a].map(|c| (c.clamp(0.0, 1.0) * 255.0).round() as u8)
+}
+
+/// Renders the background and water on the cpu, for when there is no adapter at all
+///
+/// Follows the same steps as [`Headless`](crate::Headless), so for the same parameters and time
+/// the output matches the gpu up to rounding, except that there are no ripples and the
+/// [simulation](crate::SimulationParams) is left out. Rows are rendered in parallel.
+pub struct CpuRenderer {
+    frames: Vec<Image>,
+    timeline: Timeline,
+    params: WaterParams,
+    // The rendered background, which the water reflects. Kept to save allocating every frame
+    scene: Vec<[f32; 4]>,
+    width: u32,
+    height: u32,
+}
+
+impl CpuRenderer {
+    /// Decodes the background for rendering at `width` by `height`
+    ///
+    /// # Example
+    /// ```ignore
+    /// let animation = Animation::still(image);
+    /// let mut renderer = CpuRenderer::new(&animation, WaterParams::default(), 640, 480)?;
+    /// ```
+    pub fn new(
+        animation: &Animation,
+        params: WaterParams,
+        width: u32,
+        height: u32,
+    ) -> Result<Self> {
+        ensure!(width > 0 && height > 0, "Output size must be non-zero");
+        let mut renderer = CpuRenderer {
+            frames: Vec::new(),
+            timeline: animation.timeline(),
+            params,
+            scene: vec![[0.0; 4]; (width * height) as usize],
+            width,
+            height,
+        };
+        renderer.set_background(animation);
+        Ok(renderer)
+    }
+
+    /// Changes the look of the water for the following frames
+    pub fn set_params(&mut self, params: WaterParams) {
+        self.params = params;
+    }
+
+    /// Swaps the background, keeping the output size
+    pub fn set_background(&mut self, animation: &Animation) {
+        self.frames = animation
+            .frames
+            .iter()
+            .map(|f| Image::new(&f.image))
+            .collect();
+        self.timeline = animation.timeline();
+    }
+
+    /// Renders the frame `seconds` into the animation
+    ///
+    /// # Example
+    /// ```ignore
+    /// renderer.render(2.0).save("frame.png")?;
+    /// ```
+    pub fn render(&mut self, seconds: f32) -> RgbaImage {
+        let background = &self.frames[self.timeline.frame_at(seconds)];
+        let (screen_horizon, source_horizon) = self.params.horizon.resolve(background.height);
+        let (width, height) = (self.width as f32, self.height as f32);
+
+        // The background is stretched between the top of the screen and the horizon
+        let bottom = screen_horizon + BACKGROUND_OVERLAP;
+        let scale_x = background.width as f32 / width;
+        let scale_y = background.height as f32 * source_horizon / (height * bottom);
+        let magnified = scale_x.max(scale_y) <= 1.0;
+        self.scene
+            .par_chunks_mut(self.width as usize)
+            .enumerate()
+            .for_each(|(y, row)| {
+                let screen_y = (y as f32 + 0.5) / height;
+                for (x, pixel) in row.iter_mut().enumerate() {
+                    *pixel = if screen_y < bottom {
+                        let u = (x as f32 + 0.5) / width;
+                        background.sample([u, screen_y / bottom * source_horizon], magnified)
+                    } else {
+                        [0.0; 4]
+                    };
+                }
+            });
+
+        // Below the horizon the water shows the scene mirrored and distorted
+        let scene = Image {
+            width: self.width,
+            height: self.height,
+            pixels: std::mem::take(&mut self.scene),
+        };
+        let params = &self.params;
+        let mut output = vec![0; (4 * self.width * self.height) as usize];
+        output
+            .par_chunks_mut(4 * self.width as usize)
+            .enumerate()
+            .for_each(|(y, row)| {
+                let screen_y = (y as f32 + 0.5) / height;
+                for (x, pixel) in row.chunks_mut(4).enumerate() {
+                    let color = if screen_y >= screen_horizon {
+                        let sample_coords = |x: usize, y: usize| {
+                            let u = (x as f32 + 0.5) / width;
+                            let v = 2.0 * screen_horizon - (y as f32 + 0.5) / height;
+                            noise::water_sample_coords(params, screen_horizon, [u, v], seconds)
+                        };
+                        // The gpu picks the filter from how far the sample moves across each 2x2
+                        // block of pixels, which the distortion stretches to over a texel
+                        let (qx, qy) = (x & !1, y & !1);
+                        let origin = sample_coords(qx, qy);
+                        let footprint =
+                            [sample_coords(qx + 1, qy), sample_coords(qx, qy + 1)].map(|[u, v]| {
+                                let du = (u - origin[0]) * width;
+                                let dv = (v - origin[1]) * height;
+                                du * du + dv * dv
+                            });
+                        let magnified = footprint[0].max(footprint[1]) <= 1.0;
+                        scene.sample(sample_coords(x, y), magnified)
+                    } else {
+                        scene.pixels[y * self.width as usize + x]
+                    };
+                    pixel.copy_from_slice(&to_srgb(color));
+                }
+            });
+        self.scene = scene.pixels;
+
+        RgbaImage::from_raw(self.width, self.height, output)
+            .expect("Output buffer matches the output size")
+    }
+}
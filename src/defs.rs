@@ -1,7 +1,12 @@
-use anyhow::*;
+use crate::animation::{Animation, Timeline};
+use crate::error::WaterError;
+use crate::params::{Horizon, NoiseParams, RippleParams, WaterParams};
+use crate::ripples::{Ripples, MAX_RIPPLES};
+use crate::simulation::MAX_DROPS;
 use image::GenericImageView;
+use std::collections::HashMap;
+use wgpu::include_wgsl;
 use wgpu::util::DeviceExt;
-use winit::dpi::PhysicalSize;
 
 #[repr(C)]
 #[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
@@ -9,7 +14,6 @@ use winit::dpi::PhysicalSize;
 pub struct Vertex {
     pub position: [f32; 3],
     pub tex_coords: [f32; 2],
-    pub seed: f32,
 }
 
 impl Vertex {
@@ -20,27 +24,181 @@ impl Vertex {
             step_mode: wgpu::VertexStepMode::Vertex,
             attributes: &[
                 wgpu::VertexAttribute {
-                    offset: 0,
+                    offset: std::mem::offset_of!(Vertex, position) as wgpu::BufferAddress,
                     shader_location: 0,
                     format: wgpu::VertexFormat::Float32x3,
                 },
                 wgpu::VertexAttribute {
-                    offset: std::mem::size_of::<[f32; 3]>() as wgpu::BufferAddress,
+                    offset: std::mem::offset_of!(Vertex, tex_coords) as wgpu::BufferAddress,
                     shader_location: 1,
                     format: wgpu::VertexFormat::Float32x2,
                 },
-                wgpu::VertexAttribute {
-                    offset: (std::mem::size_of::<[f32; 3]>() + std::mem::size_of::<[f32; 2]>())
-                        as wgpu::BufferAddress,
-                    shader_location: 2,
-                    format: wgpu::VertexFormat::Float32,
-                },
             ],
         }
     }
 }
 
+#[repr(C)]
+#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+/// Uniforms read by `fs_main_water`, mirrors `WaterUniforms` in shader.wgsl
+pub struct WaterUniforms {
+    /// Animation time in seconds, wrapped by [`NoiseParams::wrap`]
+    pub time: f32,
+    pub amplitude: f32,
+    pub bias: f32,
+    pub noise_scale: f32,
+    /// Texture coordinate of the horizon. The distortion falls off towards it and nothing above
+    /// it is reflected
+    pub horizon: f32,
+    pub falloff_rate: f32,
+    pub falloff_min: f32,
+    pub falloff_max: f32,
+    /// [`NoiseKind`](crate::NoiseKind) as a number, in declaration order
+    pub noise_kind: u32,
+    /// [`NoiseHash`](crate::NoiseHash) as a number, in declaration order
+    pub hash: u32,
+    pub octaves: u32,
+    pub lacunarity: f32,
+    pub gain: f32,
+    pub warp: f32,
+    /// 1 when the wind fields below are used, otherwise the noise drifts diagonally with `time`
+    pub wind: u32,
+    /// Unit vector the wind blows along
+    pub wind_x: f32,
+    pub wind_y: f32,
+    pub anisotropy: f32,
+    /// Distance the noise has moved along the wind, wrapped by [`NoiseParams::wrap`]
+    pub drift: f32,
+    /// Current strength of the gusts, scales the distortion
+    pub gust: f32,
+    /// How far the slope of the simulated water bends the reflection, 0 without a simulation
+    pub simulation: f32,
+    // Uniform buffers are sized in multiples of 16 bytes
+    _padding: [f32; 3],
+}
+
+impl WaterUniforms {
+    pub fn new(params: &WaterParams, horizon: f32, time: f32) -> Self {
+        WaterUniforms {
+            time: params.noise.wrap(time),
+            amplitude: params.amplitude,
+            bias: params.bias,
+            noise_scale: params.noise_scale,
+            horizon,
+            falloff_rate: params.falloff_rate,
+            falloff_min: params.falloff_min,
+            falloff_max: params.falloff_max,
+            noise_kind: params.noise.kind as u32,
+            hash: params.noise.hash as u32,
+            octaves: params.noise.octaves.clamp(1, NoiseParams::MAX_OCTAVES),
+            lacunarity: params.noise.lacunarity,
+            gain: params.noise.gain,
+            warp: params.noise.warp,
+            simulation: params
+                .simulation
+                .map_or(0.0, |simulation| simulation.strength),
+            ..Self::wind(params, time)
+        }
+    }
+
+    // Only the wind fields are meaningful
+    fn wind(params: &WaterParams, time: f32) -> Self {
+        let Some(wind) = params.wind else {
+            return bytemuck::Zeroable::zeroed();
+        };
+        let [wind_x, wind_y] = wind.direction_vector();
+        WaterUniforms {
+            wind: 1,
+            wind_x,
+            wind_y,
+            anisotropy: wind.anisotropy,
+            drift: params.noise.wrap(wind.drift(time)),
+            gust: wind.gust(time),
+            ..bytemuck::Zeroable::zeroed()
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+/// Ripples read by `fs_main_water`, mirrors `Ripples` in shader.wgsl
+pub struct RippleUniforms {
+    /// Number of entries of `ripples` in use
+    pub count: u32,
+    /// Width of the target over its height
+    pub aspect: f32,
+    pub speed: f32,
+    pub wavelength: f32,
+    pub damping: f32,
+    pub strength: f32,
+    // The array has to start on a 16 byte boundary
+    _padding: [f32; 2],
+    /// Centre in texture coordinates, then the age in seconds, padded to a vec4
+    pub ripples: [[f32; 4]; MAX_RIPPLES],
+}
+
+impl RippleUniforms {
+    pub fn new(params: &RippleParams, ripples: &Ripples, aspect: f32, time: f32) -> Self {
+        let mut uniforms = RippleUniforms {
+            aspect,
+            speed: params.speed,
+            wavelength: params.wavelength,
+            damping: params.damping,
+            strength: params.strength,
+            ..bytemuck::Zeroable::zeroed()
+        };
+        for ([x, y], age) in ripples.active(params, aspect, time) {
+            uniforms.ripples[uniforms.count as usize] = [x, y, age, 0.0];
+            uniforms.count += 1;
+        }
+        uniforms
+    }
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+/// Uniforms read by the compute passes in simulation.wgsl, mirrors `Step` there
+pub struct StepUniforms {
+    /// Number of cells across and down the heightfield
+    pub size: [u32; 2],
+    /// `(speed * step / cell)²` across and down, how far a wave spreads each step
+    pub courant: [f32; 2],
+    /// Width and height of a cell in screen heights
+    pub cell: [f32; 2],
+    /// Fraction of the height of the waves kept each step
+    pub keep: f32,
+    /// Number of entries of `drops` in use
+    pub drop_count: u32,
+    /// Centre in cells, then the radius and depth of the dent in screen heights
+    pub drops: [[f32; 4]; MAX_DROPS],
+}
+
+impl StepUniforms {
+    pub fn new(
+        size: [u32; 2],
+        courant: [f32; 2],
+        cell: [f32; 2],
+        keep: f32,
+        drops: &[[f32; 4]],
+    ) -> Self {
+        let mut uniforms = StepUniforms {
+            size,
+            courant,
+            cell,
+            keep,
+            drop_count: drops.len().min(MAX_DROPS) as u32,
+            ..bytemuck::Zeroable::zeroed()
+        };
+        for (slot, drop) in uniforms.drops.iter_mut().zip(drops) {
+            *slot = *drop;
+        }
+        uniforms
+    }
+}
+
 pub struct Texture {
+    // Never read directly, but owning it keeps the image alive for as long as the view
+    #[allow(dead_code)]
     pub texture: wgpu::Texture,
     pub view: wgpu::TextureView,
     pub sampler: wgpu::Sampler,
@@ -52,7 +210,7 @@ impl Texture {
         queue: &wgpu::Queue,
         bytes: &[u8],
         label: &str,
-    ) -> Result<Self> {
+    ) -> Result<Self, WaterError> {
         let img = image::load_from_memory(bytes)?;
         Self::from_image(device, queue, &img, Some(label))
     }
@@ -62,7 +220,7 @@ impl Texture {
         queue: &wgpu::Queue,
         img: &image::DynamicImage,
         label: Option<&str>,
-    ) -> Result<Self> {
+    ) -> Result<Self, WaterError> {
         let rgba = img.to_rgba8();
         let dimensions = img.dimensions();
 
@@ -119,154 +277,165 @@ impl Texture {
 pub struct Background {
     vert_buf: wgpu::Buffer,
     index_buf: wgpu::Buffer,
-    pipeline: wgpu::RenderPipeline,
-    diffuse_bind_group: wgpu::BindGroup,
+    pipelines: Pipelines,
+    texture_bind_group_layout: wgpu::BindGroupLayout,
+    // One bind group per frame of the animation
+    diffuse_bind_groups: Vec<wgpu::BindGroup>,
+    timeline: Timeline,
+    horizon: Horizon,
+    // Height of the background image in pixels, needed to place a horizon set by row
+    source_height: u32,
 }
 
 impl Background {
     pub fn new(
-        texture: Texture,
+        animation: &Animation,
         device: &wgpu::Device,
-        shader: &wgpu::ShaderModule,
-        adapter: wgpu::Adapter,
-        surface: &wgpu::Surface,
-        size: PhysicalSize<u32>,
-    ) -> Self {
-        let texture_bind_group_layout =
-            device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
-                label: Some("texture bind group layout"),
-                entries: &[
-                    wgpu::BindGroupLayoutEntry {
-                        binding: 0,
-                        visibility: wgpu::ShaderStages::FRAGMENT,
-                        ty: wgpu::BindingType::Texture {
-                            sample_type: wgpu::TextureSampleType::Float { filterable: true },
-                            view_dimension: wgpu::TextureViewDimension::D2,
-                            multisampled: false,
-                        },
-                        count: None,
-                    },
-                    wgpu::BindGroupLayoutEntry {
-                        binding: 1,
-                        visibility: wgpu::ShaderStages::FRAGMENT,
-                        ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
-                        count: None,
-                    },
-                ],
-            });
-
-        let render_pipeline_layout =
-            device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
-                label: Some("Render Pipeline Layout"),
-                bind_group_layouts: &[&texture_bind_group_layout],
-                push_constant_ranges: &[],
-            });
-        let config = wgpu::SurfaceConfiguration {
-            usage: wgpu::TextureUsages::RENDER_ATTACHMENT,
-            format: surface.get_supported_formats(&adapter)[0],
-            alpha_mode: wgpu::CompositeAlphaMode::Auto,
-            width: size.width,
-            height: size.height,
-            present_mode: wgpu::PresentMode::Fifo,
-        };
+        queue: &wgpu::Queue,
+        format: wgpu::TextureFormat,
+        horizon: Horizon,
+    ) -> Result<Self, WaterError> {
+        let texture_bind_group_layout = texture_bind_group_layout(device);
 
-        let render_pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
-            label: Some("render pipeline"),
-            layout: Some(&render_pipeline_layout),
-            vertex: wgpu::VertexState {
-                module: &shader,
-                entry_point: "vs_main",
-                buffers: &[Vertex::desc()],
-            },
-            fragment: Some(wgpu::FragmentState {
-                module: &shader,
-                entry_point: "fs_main",
-                targets: &[Some(wgpu::ColorTargetState {
-                    format: config.format,
-                    blend: Some(wgpu::BlendState::REPLACE),
-                    write_mask: wgpu::ColorWrites::all(),
-                })],
-            }),
-            primitive: wgpu::PrimitiveState {
-                topology: wgpu::PrimitiveTopology::TriangleList,
-                strip_index_format: None,
-                front_face: wgpu::FrontFace::Ccw,
-                cull_mode: Some(wgpu::Face::Back),
-                polygon_mode: wgpu::PolygonMode::Fill,
-                unclipped_depth: false,
-                conservative: false,
+        let pipelines = Pipelines::new(
+            device,
+            "background",
+            &[&texture_bind_group_layout],
+            // Frames are stored as sRGB, so they have to be encoded again for targets that
+            // don't do it themselves
+            FragmentEntry {
+                srgb: "fs_main",
+                linear: "fs_main_encode_srgb",
             },
-            depth_stencil: None,
-            multisample: wgpu::MultisampleState {
-                count: 1,
-                mask: !0,
-                alpha_to_coverage_enabled: false,
-            },
-            multiview: None,
+            format,
+        )?;
+
+        let source_height = animation.frames[0].image.height();
+        let (screen_horizon, source_horizon) = horizon.resolve(source_height);
+        let vertices = Self::vertices(screen_horizon, source_horizon);
+        let indices: &[u16] = &[2, 1, 0, 2, 3, 1];
+
+        let vertex_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Vertex Buffer"),
+            contents: bytemuck::cast_slice(&vertices),
+            usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST,
+        });
+
+        let index_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Index Buffer"),
+            contents: bytemuck::cast_slice(indices),
+            usage: wgpu::BufferUsages::INDEX,
         });
 
-        let vertices: &[Vertex] = &[
+        let diffuse_bind_groups =
+            Self::bind_frames(device, queue, &texture_bind_group_layout, animation)?;
+
+        Ok(Background {
+            vert_buf: vertex_buffer,
+            index_buf: index_buffer,
+            pipelines,
+            texture_bind_group_layout,
+            diffuse_bind_groups,
+            timeline: animation.timeline(),
+            horizon,
+            source_height,
+        })
+    }
+
+    // Fits the background, cropped at `source_horizon`, between the top of the screen and the
+    // horizon
+    fn vertices(screen_horizon: f32, source_horizon: f32) -> [Vertex; 4] {
+        // Reaches slightly below the horizon so no seam shows between it and the water
+        let bottom = 1.0 - 2.0 * screen_horizon - 0.005;
+        [
             Vertex {
                 position: [-1.0, 1.0, 1.0],
                 tex_coords: [0.0, 0.0],
-                seed: 1.4,
             },
             Vertex {
                 position: [1.0, 1.0, 1.0],
                 tex_coords: [1.0, 0.0],
-                seed: 2.9,
             },
             Vertex {
-                position: [-1.0, -0.005, 1.0],
-                tex_coords: [0.0, 1.0],
-                seed: 9.9,
+                position: [-1.0, bottom, 1.0],
+                tex_coords: [0.0, source_horizon],
             },
             Vertex {
-                position: [1.0, -0.005, 1.0],
-                tex_coords: [1.0, 1.0],
-                seed: 0.0,
+                position: [1.0, bottom, 1.0],
+                tex_coords: [1.0, source_horizon],
             },
-        ];
-        let indices: &[u16] = &[2, 1, 0, 2, 3, 1];
+        ]
+    }
 
-        let vertex_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
-            label: Some("Vertex Buffer"),
-            contents: bytemuck::cast_slice(vertices),
-            usage: wgpu::BufferUsages::VERTEX,
-        });
+    /// Moves the horizon, returning its height as a fraction of the screen for the water
+    ///
+    /// # Example
+    /// ```ignore
+    /// let horizon = background.set_horizon(&queue, Horizon::SourceRow(310));
+    /// water.set_horizon(&queue, horizon);
+    /// ```
+    pub fn set_horizon(&mut self, queue: &wgpu::Queue, horizon: Horizon) -> f32 {
+        self.horizon = horizon;
+        let (screen_horizon, source_horizon) = horizon.resolve(self.source_height);
+        let vertices = Self::vertices(screen_horizon, source_horizon);
+        queue.write_buffer(&self.vert_buf, 0, bytemuck::cast_slice(&vertices));
+        screen_horizon
+    }
 
-        let index_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
-            label: Some("Index Buffer"),
-            contents: bytemuck::cast_slice(indices),
-            usage: wgpu::BufferUsages::INDEX,
-        });
+    /// Height of the horizon as a fraction of the screen
+    pub fn screen_horizon(&self) -> f32 {
+        self.horizon.resolve(self.source_height).0
+    }
 
-        let texture_bind_group_layout =
-            device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
-                label: Some("Texture Bind Group Layout"),
-                entries: &[
-                    wgpu::BindGroupLayoutEntry {
-                        binding: 0,
-                        visibility: wgpu::ShaderStages::FRAGMENT,
-                        ty: wgpu::BindingType::Texture {
-                            sample_type: wgpu::TextureSampleType::Float { filterable: true },
-                            view_dimension: wgpu::TextureViewDimension::D2,
-                            multisampled: false,
-                        },
-                        count: None,
-                    },
-                    wgpu::BindGroupLayoutEntry {
-                        binding: 1,
-                        visibility: wgpu::ShaderStages::FRAGMENT,
-                        ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
-                        count: None,
-                    },
-                ],
-            });
+    /// Replaces the image or animation drawn as the background
+    ///
+    /// A horizon given as a row is moved to match the new image, so call
+    /// [`Background::screen_horizon`] afterwards to keep the water in line.
+    ///
+    /// # Example
+    /// ```ignore
+    /// let animation = Animation::load(Path::new("flag.gif"), 12.0)?;
+    /// background.set_animation(&device, &queue, &animation)?;
+    /// ```
+    pub fn set_animation(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        animation: &Animation,
+    ) -> Result<(), WaterError> {
+        self.diffuse_bind_groups =
+            Self::bind_frames(device, queue, &self.texture_bind_group_layout, animation)?;
+        self.timeline = animation.timeline();
+        // A horizon set by row has to follow the size of the new image
+        self.source_height = animation.frames[0].image.height();
+        self.set_horizon(queue, self.horizon);
+        Ok(())
+    }
 
-        let diffuse_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+    fn bind_frames(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        layout: &wgpu::BindGroupLayout,
+        animation: &Animation,
+    ) -> Result<Vec<wgpu::BindGroup>, WaterError> {
+        animation
+            .frames
+            .iter()
+            .map(|frame| {
+                let texture = Texture::from_image(device, queue, &frame.image, Some("Background"))?;
+                Ok(Self::bind_texture(device, layout, &texture))
+            })
+            .collect()
+    }
+
+    fn bind_texture(
+        device: &wgpu::Device,
+        layout: &wgpu::BindGroupLayout,
+        texture: &Texture,
+    ) -> wgpu::BindGroup {
+        device.create_bind_group(&wgpu::BindGroupDescriptor {
             label: Some("Diffuse Bind Group"),
-            layout: &texture_bind_group_layout,
+            layout,
             entries: &[
                 wgpu::BindGroupEntry {
                     binding: 0,
@@ -277,118 +446,130 @@ impl Background {
                     resource: wgpu::BindingResource::Sampler(&texture.sampler),
                 },
             ],
-        });
-
-        Background {
-            vert_buf: vertex_buffer,
-            index_buf: index_buffer,
-            pipeline: render_pipeline,
-            diffuse_bind_group,
-        }
+        })
     }
 
-    pub fn draw(&self, output: &wgpu::Texture, device: &wgpu::Device, queue: &wgpu::Queue) {
-        let view = output.create_view(&wgpu::TextureViewDescriptor::default());
-        let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
-            label: Some("Encoder"),
+    /// Records a pass drawing the frame of the background shown `seconds` into its animation
+    ///
+    /// # Example
+    /// ```ignore
+    /// background.draw(&mut encoder, &view, clock.seconds());
+    /// queue.submit(std::iter::once(encoder.finish()));
+    /// ```
+    pub fn draw(&self, encoder: &mut wgpu::CommandEncoder, view: &wgpu::TextureView, seconds: f32) {
+        let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("Wgpu render pass"),
+            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                view,
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Load,
+                    store: true,
+                },
+            })],
+            depth_stencil_attachment: None,
         });
 
-        {
-            let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
-                label: Some("Wgpu render pass"),
-                color_attachments: &[Some(wgpu::RenderPassColorAttachment {
-                    view: &view,
-                    resolve_target: None,
-                    ops: wgpu::Operations {
-                        load: wgpu::LoadOp::Load,
-                        store: true,
-                    },
-                })],
-                depth_stencil_attachment: None,
-            });
-
-            render_pass.set_pipeline(&self.pipeline);
-            render_pass.set_bind_group(0, &self.diffuse_bind_group, &[]);
-            render_pass.set_vertex_buffer(0, self.vert_buf.slice(..));
-            render_pass.set_index_buffer(self.index_buf.slice(..), wgpu::IndexFormat::Uint16);
-            render_pass.draw_indexed(0..6, 0, 0..1);
-        }
-
-        queue.submit(std::iter::once(encoder.finish()));
+        render_pass.set_pipeline(self.pipelines.current());
+        let frame = self.timeline.frame_at(seconds);
+        render_pass.set_bind_group(0, &self.diffuse_bind_groups[frame], &[]);
+        render_pass.set_vertex_buffer(0, self.vert_buf.slice(..));
+        render_pass.set_index_buffer(self.index_buf.slice(..), wgpu::IndexFormat::Uint16);
+        render_pass.draw_indexed(0..6, 0, 0..1);
     }
 }
 
-pub struct Water {
-    pub indices: wgpu::Buffer,
-    pub vertex: wgpu::Buffer,
-    vertex_array: [Vertex; 4],
-    render_pipeline: wgpu::RenderPipeline,
-    texture_bind_group_layout: wgpu::BindGroupLayout,
-    time: std::time::SystemTime,
+/// Fragment shader entry points for targets that do and don't encode sRGB when written to
+pub struct FragmentEntry {
+    pub srgb: &'static str,
+    pub linear: &'static str,
+}
+
+/// Render pipelines of one pass, built for each target format the first time it is used
+pub struct Pipelines {
+    name: &'static str,
+    shader: wgpu::ShaderModule,
+    layout: wgpu::PipelineLayout,
+    entry: FragmentEntry,
+    pipelines: HashMap<wgpu::TextureFormat, wgpu::RenderPipeline>,
+    format: wgpu::TextureFormat,
 }
 
-impl Water {
+impl Pipelines {
+    /// Compiles the shader and builds the pipeline for `format`
+    ///
+    /// `name` says which pass failed in errors.
     pub fn new(
         device: &wgpu::Device,
-        shader: &wgpu::ShaderModule,
-        config: &wgpu::SurfaceConfiguration,
-        texture_bind_group_layout: &wgpu::BindGroupLayout,
-    ) -> Self {
-        let vertices: [Vertex; 4] = [
-            Vertex {
-                position: [-1.0, -0.005, 0.0],
-                tex_coords: [0.0, 0.5],
-                seed: 0.0,
-            },
-            Vertex {
-                position: [1.0, -0.005, 0.0],
-                tex_coords: [1.0, 0.5],
-                seed: 0.0,
-            },
-            Vertex {
-                position: [-1.0, -1.0, 0.0],
-                tex_coords: [0.0, 0.0],
-                seed: 0.0,
-            },
-            Vertex {
-                position: [1.0, -1.0, 0.0],
-                tex_coords: [1.0, 0.0],
-                seed: 0.0,
-            },
-        ];
-
-        let vertex_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
-            label: Some("Vertex Buffer"),
-            contents: bytemuck::cast_slice(&vertices),
-            usage: wgpu::BufferUsages::VERTEX,
+        name: &'static str,
+        bind_group_layouts: &[&wgpu::BindGroupLayout],
+        entry: FragmentEntry,
+        format: wgpu::TextureFormat,
+    ) -> Result<Self, WaterError> {
+        device.push_error_scope(wgpu::ErrorFilter::Validation);
+        let shader = device.create_shader_module(include_wgsl!("shader.wgsl"));
+        let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Render Pipeline Layout"),
+            bind_group_layouts,
+            push_constant_ranges: &[],
         });
+        Self::check(device, name)?;
 
-        let indices: &[u16] = &[2, 1, 0, 2, 3, 1];
-        let index_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
-            label: Some("Index Buffer"),
-            contents: bytemuck::cast_slice(indices),
-            usage: wgpu::BufferUsages::INDEX,
-        });
+        let mut pipelines = Pipelines {
+            name,
+            shader,
+            layout,
+            entry,
+            pipelines: HashMap::new(),
+            format,
+        };
+        pipelines.set_format(device, format)?;
+        Ok(pipelines)
+    }
+
+    /// Pops the error scope pushed before creating gpu objects, turning anything caught into an
+    /// error instead of the default panic
+    pub(crate) fn check(device: &wgpu::Device, name: &'static str) -> Result<(), WaterError> {
+        // Native backends report errors straight away, so this never actually waits
+        match pollster::block_on(device.pop_error_scope()) {
+            Some(error) => Err(WaterError::Shader {
+                pipeline: name,
+                description: error.to_string(),
+            }),
+            None => Ok(()),
+        }
+    }
+
+    /// Makes the pipeline for `format` current, building it if this format hasn't been used yet
+    pub fn set_format(
+        &mut self,
+        device: &wgpu::Device,
+        format: wgpu::TextureFormat,
+    ) -> Result<(), WaterError> {
+        if self.pipelines.contains_key(&format) {
+            self.format = format;
+            return Ok(());
+        }
 
-        let render_pipeline_layout =
-            device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
-                label: Some("render_pipeline_layout"),
-                bind_group_layouts: &[&texture_bind_group_layout],
-                push_constant_ranges: &[],
-            });
-        let render_pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+        let entry_point = if format.describe().srgb {
+            self.entry.srgb
+        } else {
+            self.entry.linear
+        };
+        device.push_error_scope(wgpu::ErrorFilter::Validation);
+        let pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
             label: Some("render pipeline"),
-            layout: Some(&render_pipeline_layout),
+            layout: Some(&self.layout),
             vertex: wgpu::VertexState {
-                module: &shader,
+                module: &self.shader,
                 entry_point: "vs_main",
                 buffers: &[Vertex::desc()],
             },
             fragment: Some(wgpu::FragmentState {
-                module: &shader,
-                entry_point: "fs_main_water",
+                module: &self.shader,
+                entry_point,
                 targets: &[Some(wgpu::ColorTargetState {
-                    format: config.format,
+                    format,
                     blend: Some(wgpu::BlendState::REPLACE),
                     write_mask: wgpu::ColorWrites::all(),
                 })],
@@ -410,124 +591,506 @@ impl Water {
             },
             multiview: None,
         });
+        Self::check(device, self.name)?;
+        self.pipelines.insert(format, pipeline);
+        self.format = format;
+        Ok(())
+    }
 
-        let texture_bind_group_layout =
-            device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
-                label: Some("texture bind group layout"),
-                entries: &[
-                    wgpu::BindGroupLayoutEntry {
-                        binding: 0,
-                        visibility: wgpu::ShaderStages::FRAGMENT,
-                        ty: wgpu::BindingType::Texture {
-                            sample_type: wgpu::TextureSampleType::Float { filterable: true },
-                            view_dimension: wgpu::TextureViewDimension::D2,
-                            multisampled: false,
-                        },
-                        count: None,
-                    },
-                    wgpu::BindGroupLayoutEntry {
-                        binding: 1,
-                        visibility: wgpu::ShaderStages::FRAGMENT,
-                        ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
-                        count: None,
-                    },
-                ],
-            });
-
-        Water {
-            indices: index_buffer,
-            vertex: vertex_buffer,
-            vertex_array: vertices,
-            render_pipeline,
-            texture_bind_group_layout,
-            time: std::time::SystemTime::now(),
-        }
+    /// Pipeline for the format last passed to [`Pipelines::set_format`]
+    pub fn current(&self) -> &wgpu::RenderPipeline {
+        &self.pipelines[&self.format]
     }
 
-    fn regen(&mut self, device: &wgpu::Device) {
-        let seed = self.time.elapsed().unwrap().as_secs_f32();
+    /// Format last passed to [`Pipelines::set_format`]
+    pub fn format(&self) -> wgpu::TextureFormat {
+        self.format
+    }
+}
 
-        self.vertex_array = [
-            Vertex {
-                position: [-1.0, 0.0, 0.0],
-                tex_coords: [0.0, 0.5],
-                seed,
-            },
-            Vertex {
-                position: [1.0, 0.0, 0.0],
-                tex_coords: [1.0, 0.5],
-                seed,
-            },
-            Vertex {
-                position: [-1.0, -1.0, 0.0],
-                tex_coords: [0.0, 0.0],
-                seed,
-            },
-            Vertex {
-                position: [1.0, -1.0, 0.0],
-                tex_coords: [1.0, 0.0],
-                seed,
-            },
-        ];
+/// Layout of `t_diffuse` and `s_diffuse`, group 0 of both pipelines
+pub const TEXTURE_LAYOUT_ENTRIES: [wgpu::BindGroupLayoutEntry; 2] = [
+    wgpu::BindGroupLayoutEntry {
+        binding: 0,
+        visibility: wgpu::ShaderStages::FRAGMENT,
+        ty: wgpu::BindingType::Texture {
+            sample_type: wgpu::TextureSampleType::Float { filterable: true },
+            view_dimension: wgpu::TextureViewDimension::D2,
+            multisampled: false,
+        },
+        count: None,
+    },
+    wgpu::BindGroupLayoutEntry {
+        binding: 1,
+        visibility: wgpu::ShaderStages::FRAGMENT,
+        ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+        count: None,
+    },
+];
 
-        self.vertex = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
-            label: Some("Vertex buffer"),
-            contents: bytemuck::cast_slice(&self.vertex_array),
-            usage: wgpu::BufferUsages::VERTEX,
-        })
+/// Layout of the `water` uniforms, group 1 of the water pipeline
+pub const WATER_UNIFORM_LAYOUT_ENTRIES: [wgpu::BindGroupLayoutEntry; 2] = [
+    wgpu::BindGroupLayoutEntry {
+        binding: 0,
+        visibility: wgpu::ShaderStages::FRAGMENT,
+        ty: wgpu::BindingType::Buffer {
+            ty: wgpu::BufferBindingType::Uniform,
+            has_dynamic_offset: false,
+            min_binding_size: None,
+        },
+        count: None,
+    },
+    // Ripples, a uniform rather than storage buffer as downlevel adapters may have none of those
+    wgpu::BindGroupLayoutEntry {
+        binding: 1,
+        visibility: wgpu::ShaderStages::FRAGMENT,
+        ty: wgpu::BindingType::Buffer {
+            ty: wgpu::BufferBindingType::Uniform,
+            has_dynamic_offset: false,
+            min_binding_size: None,
+        },
+        count: None,
+    },
+];
+
+/// Layout of the simulated heightfield, group 2 of the simulated water pipeline
+pub const HEIGHTFIELD_LAYOUT_ENTRIES: [wgpu::BindGroupLayoutEntry; 2] = [
+    // Heights, storage buffers rather than textures as the GL backend can't write textures from
+    // compute shaders
+    storage_entry(0, wgpu::ShaderStages::FRAGMENT, true),
+    // The size of the heightfield, from the simulation's uniforms
+    wgpu::BindGroupLayoutEntry {
+        binding: 1,
+        visibility: wgpu::ShaderStages::FRAGMENT,
+        ty: wgpu::BindingType::Buffer {
+            ty: wgpu::BufferBindingType::Uniform,
+            has_dynamic_offset: false,
+            min_binding_size: None,
+        },
+        count: None,
+    },
+];
+
+/// Layout of the compute passes in simulation.wgsl, which read one heightfield and write the next
+pub const SIMULATION_LAYOUT_ENTRIES: [wgpu::BindGroupLayoutEntry; 3] = [
+    wgpu::BindGroupLayoutEntry {
+        binding: 0,
+        visibility: wgpu::ShaderStages::COMPUTE,
+        ty: wgpu::BindingType::Buffer {
+            ty: wgpu::BufferBindingType::Uniform,
+            has_dynamic_offset: false,
+            min_binding_size: None,
+        },
+        count: None,
+    },
+    storage_entry(1, wgpu::ShaderStages::COMPUTE, true),
+    storage_entry(2, wgpu::ShaderStages::COMPUTE, false),
+];
+
+const fn storage_entry(
+    binding: u32,
+    visibility: wgpu::ShaderStages,
+    read_only: bool,
+) -> wgpu::BindGroupLayoutEntry {
+    wgpu::BindGroupLayoutEntry {
+        binding,
+        visibility,
+        ty: wgpu::BindingType::Buffer {
+            ty: wgpu::BufferBindingType::Storage { read_only },
+            has_dynamic_offset: false,
+            min_binding_size: None,
+        },
+        count: None,
     }
+}
 
-    pub fn draw(
-        &mut self,
-        device: &wgpu::Device,
-        view: &wgpu::TextureView,
-        sampler: &wgpu::Sampler,
-        queue: &wgpu::Queue,
-        input: &wgpu::Texture,
-    ) {
-        self.regen(device);
+/// Creates the bind group layout for sampling a single 2D texture in the fragment stage
+pub fn texture_bind_group_layout(device: &wgpu::Device) -> wgpu::BindGroupLayout {
+    device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+        label: Some("Texture Bind Group Layout"),
+        entries: &TEXTURE_LAYOUT_ENTRIES,
+    })
+}
 
-        let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
-            label: Some("Encoder 1"),
-        });
-        let diffuse_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
-            label: Some("Diffuse Bind Group"),
-            layout: &self.texture_bind_group_layout,
-            entries: &[
-                wgpu::BindGroupEntry {
-                    binding: 0,
-                    resource: wgpu::BindingResource::TextureView(
-                        &input.create_view(&wgpu::TextureViewDescriptor::default()),
-                    ),
-                },
-                wgpu::BindGroupEntry {
-                    binding: 1,
-                    resource: wgpu::BindingResource::Sampler(&sampler),
-                },
+/// Creates the sampler used by the water pass to read the rendered scene
+pub fn scene_sampler(device: &wgpu::Device) -> wgpu::Sampler {
+    device.create_sampler(&wgpu::SamplerDescriptor {
+        address_mode_u: wgpu::AddressMode::ClampToEdge,
+        address_mode_v: wgpu::AddressMode::ClampToEdge,
+        address_mode_w: wgpu::AddressMode::ClampToEdge,
+        mag_filter: wgpu::FilterMode::Linear,
+        min_filter: wgpu::FilterMode::Nearest,
+        mipmap_filter: wgpu::FilterMode::Nearest,
+        ..Default::default()
+    })
+}
+
+// Checks shader.wgsl against the structs and layouts above, so a mismatch fails `cargo test`
+// rather than the pipeline creation at startup
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use naga::{Binding, ImageClass, ImageDimension, ScalarKind, ShaderStage, TypeInner};
+
+    const SHADER: &str = include_str!("shader.wgsl");
+    const SIMULATION_SHADER: &str = include_str!("simulation.wgsl");
+
+    fn parse() -> (naga::Module, naga::valid::ModuleInfo) {
+        parse_source(SHADER)
+    }
+
+    fn parse_source(source: &str) -> (naga::Module, naga::valid::ModuleInfo) {
+        let module = naga::front::wgsl::parse_str(source)
+            .unwrap_or_else(|e| panic!("{}", e.emit_to_string(source)));
+        let info = naga::valid::Validator::new(
+            naga::valid::ValidationFlags::all(),
+            naga::valid::Capabilities::empty(),
+        )
+        .validate(&module)
+        .unwrap_or_else(|e| panic!("shader is invalid: {:?}", e));
+        (module, info)
+    }
+
+    fn entry_point(module: &naga::Module, name: &str) -> (usize, ShaderStage) {
+        module
+            .entry_points
+            .iter()
+            .enumerate()
+            .find(|(_, entry)| entry.name == name)
+            .map(|(index, entry)| (index, entry.stage))
+            .unwrap_or_else(|| panic!("shader.wgsl has no entry point {}", name))
+    }
+
+    // Groups each entry point is run with, in pipeline layout order
+    fn layouts(entry: &str) -> Vec<&'static [wgpu::BindGroupLayoutEntry]> {
+        match entry {
+            "fs_main" | "fs_main_encode_srgb" => vec![&TEXTURE_LAYOUT_ENTRIES],
+            "fs_main_water" => vec![&TEXTURE_LAYOUT_ENTRIES, &WATER_UNIFORM_LAYOUT_ENTRIES],
+            "fs_main_water_simulated" => vec![
+                &TEXTURE_LAYOUT_ENTRIES,
+                &WATER_UNIFORM_LAYOUT_ENTRIES,
+                &HEIGHTFIELD_LAYOUT_ENTRIES,
             ],
-        });
+            "add_drops" | "step_waves" => vec![&SIMULATION_LAYOUT_ENTRIES],
+            _ => vec![],
+        }
+    }
+
+    #[test]
+    fn shader_is_valid() {
+        parse();
+        parse_source(SIMULATION_SHADER);
+    }
+
+    #[test]
+    fn entry_points_exist() {
+        let (module, _) = parse();
+        assert_eq!(entry_point(&module, "vs_main").1, ShaderStage::Vertex);
+        for name in [
+            "fs_main",
+            "fs_main_encode_srgb",
+            "fs_main_water",
+            "fs_main_water_simulated",
+        ] {
+            assert_eq!(entry_point(&module, name).1, ShaderStage::Fragment);
+        }
+        let (module, _) = parse_source(SIMULATION_SHADER);
+        for name in ["add_drops", "step_waves"] {
+            assert_eq!(entry_point(&module, name).1, ShaderStage::Compute);
+        }
+    }
+
+    #[test]
+    fn vertex_inputs_match_vertex_desc() {
+        let (module, _) = parse();
+        let (index, _) = entry_point(&module, "vs_main");
 
-        {
-            let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
-                label: Some("Wgpu render pass"),
-                color_attachments: &[Some(wgpu::RenderPassColorAttachment {
-                    view,
-                    resolve_target: None,
-                    ops: wgpu::Operations {
-                        load: wgpu::LoadOp::Load,
-                        store: true,
+        // Locations read by vs_main, whether passed directly or as members of a struct
+        let mut inputs = Vec::new();
+        for argument in &module.entry_points[index].function.arguments {
+            match (&argument.binding, &module.types[argument.ty].inner) {
+                (Some(Binding::Location { location, .. }), _) => {
+                    inputs.push((*location, argument.ty))
+                }
+                (None, TypeInner::Struct { members, .. }) => {
+                    for member in members {
+                        if let Some(Binding::Location { location, .. }) = member.binding {
+                            inputs.push((location, member.ty));
+                        }
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        let desc = Vertex::desc();
+        assert_eq!(
+            desc.array_stride,
+            std::mem::size_of::<Vertex>() as wgpu::BufferAddress
+        );
+        // Only the position and texture coordinates, every other input comes from uniforms
+        let locations: Vec<u32> = inputs.iter().map(|&(location, _)| location).collect();
+        assert_eq!(locations, [0, 1]);
+        assert_eq!(
+            inputs.len(),
+            desc.attributes.len(),
+            "vs_main reads {} locations but Vertex::desc() has {} attributes",
+            inputs.len(),
+            desc.attributes.len()
+        );
+        for (location, ty) in inputs {
+            let attribute = desc
+                .attributes
+                .iter()
+                .find(|attribute| attribute.shader_location == location)
+                .unwrap_or_else(|| panic!("Vertex::desc() has nothing at location {}", location));
+            let components = match module.types[ty].inner {
+                TypeInner::Scalar {
+                    kind: ScalarKind::Float,
+                    width: 4,
+                } => 1,
+                TypeInner::Vector {
+                    size,
+                    kind: ScalarKind::Float,
+                    width: 4,
+                } => size as u64,
+                ref other => panic!("Unexpected vertex input at {}: {:?}", location, other),
+            };
+            let expected = match attribute.format {
+                wgpu::VertexFormat::Float32 => 1,
+                wgpu::VertexFormat::Float32x2 => 2,
+                wgpu::VertexFormat::Float32x3 => 3,
+                wgpu::VertexFormat::Float32x4 => 4,
+                other => panic!("Unexpected vertex format {:?}", other),
+            };
+            assert_eq!(
+                components, expected,
+                "location {} has {} components in the shader but {:?} in Vertex::desc()",
+                location, components, attribute.format
+            );
+        }
+    }
+
+    #[test]
+    fn bind_groups_match_layouts() {
+        for source in [SHADER, SIMULATION_SHADER] {
+            check_bind_groups(source);
+        }
+    }
+
+    fn check_bind_groups(source: &str) {
+        let (module, info) = parse_source(source);
+        for (index, entry) in module.entry_points.iter().enumerate() {
+            let layouts = layouts(&entry.name);
+            let stage = match entry.stage {
+                ShaderStage::Vertex => wgpu::ShaderStages::VERTEX,
+                ShaderStage::Fragment => wgpu::ShaderStages::FRAGMENT,
+                ShaderStage::Compute => wgpu::ShaderStages::COMPUTE,
+            };
+            let uses = info.get_entry_point(index);
+
+            for (handle, global) in module.global_variables.iter() {
+                let Some(binding) = &global.binding else {
+                    continue;
+                };
+                if uses[handle].is_empty() {
+                    continue;
+                }
+
+                let name = global.name.as_deref().unwrap_or("?");
+                let layout_entry = layouts
+                    .get(binding.group as usize)
+                    .and_then(|entries| entries.iter().find(|e| e.binding == binding.binding))
+                    .unwrap_or_else(|| {
+                        panic!(
+                            "{} uses {} at group {} binding {}, \
+                             which its pipeline layout doesn't have",
+                            entry.name, name, binding.group, binding.binding
+                        )
+                    });
+                assert!(
+                    layout_entry.visibility.contains(stage),
+                    "{} isn't visible to {}",
+                    name,
+                    entry.name
+                );
+
+                let matches = match (&module.types[global.ty].inner, layout_entry.ty) {
+                    (
+                        TypeInner::Image {
+                            dim: ImageDimension::D2,
+                            arrayed: false,
+                            class:
+                                ImageClass::Sampled {
+                                    kind: ScalarKind::Float,
+                                    multi,
+                                },
+                        },
+                        wgpu::BindingType::Texture {
+                            sample_type: wgpu::TextureSampleType::Float { .. },
+                            view_dimension: wgpu::TextureViewDimension::D2,
+                            multisampled,
+                        },
+                    ) => *multi == multisampled,
+                    (
+                        TypeInner::Sampler { comparison: false },
+                        wgpu::BindingType::Sampler(
+                            wgpu::SamplerBindingType::Filtering
+                            | wgpu::SamplerBindingType::NonFiltering,
+                        ),
+                    ) => true,
+                    (
+                        TypeInner::Struct { .. },
+                        wgpu::BindingType::Buffer {
+                            ty: wgpu::BufferBindingType::Uniform,
+                            ..
+                        },
+                    ) => global.space == naga::AddressSpace::Uniform,
+                    (
+                        TypeInner::Array { .. } | TypeInner::Struct { .. },
+                        wgpu::BindingType::Buffer {
+                            ty: wgpu::BufferBindingType::Storage { read_only },
+                            ..
+                        },
+                    ) => match global.space {
+                        naga::AddressSpace::Storage { access } => {
+                            access.contains(naga::StorageAccess::STORE) != read_only
+                        }
+                        _ => false,
                     },
-                })],
-                depth_stencil_attachment: None,
-            });
-
-            render_pass.set_pipeline(&self.render_pipeline);
-            render_pass.set_bind_group(0, &diffuse_bind_group, &[]);
-            render_pass.set_vertex_buffer(0, self.vertex.slice(..));
-            render_pass.set_index_buffer(self.indices.slice(..), wgpu::IndexFormat::Uint16);
-            render_pass.draw_indexed(0..6, 0, 0..1);
+                    _ => false,
+                };
+                assert!(
+                    matches,
+                    "{} in {} doesn't match its layout entry {:?}",
+                    name, entry.name, layout_entry.ty
+                );
+            }
         }
+    }
 
-        queue.submit(std::iter::once(encoder.finish()));
+    // Checks the struct called `name` in the shader has the members of its Rust copy, at the same
+    // offsets, and fits in `size` bytes
+    fn check_struct(source: &str, name: &str, offsets: &[(&str, usize)], size: usize) {
+        let (module, _) = parse_source(source);
+        let (_, ty) = module
+            .types
+            .iter()
+            .find(|(_, ty)| ty.name.as_deref() == Some(name))
+            .unwrap_or_else(|| panic!("the shader has no {} struct", name));
+        let TypeInner::Struct { members, span } = &ty.inner else {
+            panic!("{} isn't a struct", name);
+        };
+
+        assert_eq!(members.len(), offsets.len());
+        for (member, &(name, offset)) in members.iter().zip(offsets) {
+            assert_eq!(member.name.as_deref(), Some(name));
+            assert_eq!(
+                member.offset as usize, offset,
+                "{} is at the wrong offset",
+                name
+            );
+        }
+        assert!(*span as usize <= size);
+    }
+
+    #[test]
+    fn water_uniforms_match_shader_struct() {
+        let offsets = [
+            ("time", std::mem::offset_of!(WaterUniforms, time)),
+            ("amplitude", std::mem::offset_of!(WaterUniforms, amplitude)),
+            ("bias", std::mem::offset_of!(WaterUniforms, bias)),
+            (
+                "noise_scale",
+                std::mem::offset_of!(WaterUniforms, noise_scale),
+            ),
+            ("horizon", std::mem::offset_of!(WaterUniforms, horizon)),
+            (
+                "falloff_rate",
+                std::mem::offset_of!(WaterUniforms, falloff_rate),
+            ),
+            (
+                "falloff_min",
+                std::mem::offset_of!(WaterUniforms, falloff_min),
+            ),
+            (
+                "falloff_max",
+                std::mem::offset_of!(WaterUniforms, falloff_max),
+            ),
+            (
+                "noise_kind",
+                std::mem::offset_of!(WaterUniforms, noise_kind),
+            ),
+            ("hash", std::mem::offset_of!(WaterUniforms, hash)),
+            ("octaves", std::mem::offset_of!(WaterUniforms, octaves)),
+            (
+                "lacunarity",
+                std::mem::offset_of!(WaterUniforms, lacunarity),
+            ),
+            ("gain", std::mem::offset_of!(WaterUniforms, gain)),
+            ("warp", std::mem::offset_of!(WaterUniforms, warp)),
+            ("wind", std::mem::offset_of!(WaterUniforms, wind)),
+            ("wind_x", std::mem::offset_of!(WaterUniforms, wind_x)),
+            ("wind_y", std::mem::offset_of!(WaterUniforms, wind_y)),
+            (
+                "anisotropy",
+                std::mem::offset_of!(WaterUniforms, anisotropy),
+            ),
+            ("drift", std::mem::offset_of!(WaterUniforms, drift)),
+            ("gust", std::mem::offset_of!(WaterUniforms, gust)),
+            (
+                "simulation",
+                std::mem::offset_of!(WaterUniforms, simulation),
+            ),
+        ];
+        check_struct(
+            SHADER,
+            "WaterUniforms",
+            &offsets,
+            std::mem::size_of::<WaterUniforms>(),
+        );
+    }
+
+    #[test]
+    fn ripple_uniforms_match_shader_struct() {
+        let offsets = [
+            ("count", std::mem::offset_of!(RippleUniforms, count)),
+            ("aspect", std::mem::offset_of!(RippleUniforms, aspect)),
+            ("speed", std::mem::offset_of!(RippleUniforms, speed)),
+            (
+                "wavelength",
+                std::mem::offset_of!(RippleUniforms, wavelength),
+            ),
+            ("damping", std::mem::offset_of!(RippleUniforms, damping)),
+            ("strength", std::mem::offset_of!(RippleUniforms, strength)),
+            ("ripples", std::mem::offset_of!(RippleUniforms, ripples)),
+        ];
+        check_struct(
+            SHADER,
+            "Ripples",
+            &offsets,
+            std::mem::size_of::<RippleUniforms>(),
+        );
+    }
+
+    #[test]
+    fn step_uniforms_match_shader_struct() {
+        let offsets = [
+            ("size", std::mem::offset_of!(StepUniforms, size)),
+            ("courant", std::mem::offset_of!(StepUniforms, courant)),
+            ("cell", std::mem::offset_of!(StepUniforms, cell)),
+            ("keep", std::mem::offset_of!(StepUniforms, keep)),
+            ("drop_count", std::mem::offset_of!(StepUniforms, drop_count)),
+            ("drops", std::mem::offset_of!(StepUniforms, drops)),
+        ];
+        // The water pass reads the same buffer
+        for source in [SIMULATION_SHADER, SHADER] {
+            check_struct(
+                source,
+                "Step",
+                &offsets,
+                std::mem::size_of::<StepUniforms>(),
+            );
+        }
     }
 }
@@ -0,0 +1,63 @@
+use crate::adapter::{AdapterChoice, Backend};
+use std::path::PathBuf;
+
+/// Everything that can go wrong setting up or running the water effect
+///
+/// Non exhaustive, as which variants exist depends on the enabled features.
+#[derive(Debug, thiserror::Error)]
+#[non_exhaustive]
+pub enum WaterError {
+    /// No adapter was found on `backend`, not even a software one
+    #[error("No {} graphics adapter found, not even a software one", backend_name(*.backend))]
+    NoAdapter { backend: Backend },
+    /// No adapter matches the one asked for
+    #[error("No adapter matches {choice}, run with --list-adapters to see the choices")]
+    UnknownAdapter { choice: AdapterChoice },
+    /// The adapter can't draw to the window
+    #[error("{adapter} can't draw to this window")]
+    Adapter { adapter: String },
+    /// The adapter refused to open a device
+    #[error("Failed to open a device on {adapter}")]
+    Device {
+        adapter: String,
+        #[source]
+        source: wgpu::RequestDeviceError,
+    },
+    /// An image could not be decoded
+    #[error("Failed to decode image")]
+    Image(#[from] image::ImageError),
+    /// The shader or a pipeline built from it failed validation
+    #[error("Failed to build the {pipeline} pipeline: {description}")]
+    Shader {
+        pipeline: &'static str,
+        description: String,
+    },
+    /// The window surface could not be drawn to
+    #[error("Failed to draw to the window surface")]
+    Surface(#[from] wgpu::SurfaceError),
+    /// The window could not be created
+    #[cfg(feature = "viewer")]
+    #[error("Failed to create the window")]
+    Window(#[from] winit::error::OsError),
+    /// The background could not be loaded from `path`, or the embedded image when `None`
+    #[error("Failed to load the background")]
+    Background {
+        path: Option<PathBuf>,
+        #[source]
+        source: anyhow::Error,
+    },
+    /// The parameters file at `path` could not be read
+    #[error("Failed to load the water parameters from {}", .path.display())]
+    Params {
+        path: PathBuf,
+        #[source]
+        source: anyhow::Error,
+    },
+}
+
+fn backend_name(backend: Backend) -> String {
+    match backend {
+        Backend::All => "suitable".to_owned(),
+        backend => format!("{:?}", backend),
+    }
+}
@@ -0,0 +1,316 @@
+use crate::adapter::{self, AdapterOptions};
+use crate::animation::{Animation, BackgroundSource};
+use crate::clock::Clock;
+use crate::cpu::CpuRenderer;
+use crate::defs;
+use crate::error::WaterError;
+use crate::params::WaterParams;
+use crate::renderer::WaterRenderer;
+use crate::simulation::FALLBACK_RESOLUTION;
+use crate::targets::RenderTarget;
+use anyhow::*;
+use std::path::Path;
+
+// Offscreen targets are always RGBA so the read back bytes can go straight into an image
+const FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba8UnormSrgb;
+
+/// Renders the background and water passes into an offscreen texture instead of a window
+pub struct Headless {
+    device: wgpu::Device,
+    queue: wgpu::Queue,
+    background: defs::Background,
+    water: WaterRenderer,
+    target: RenderTarget,
+    scene: RenderTarget,
+    readback: wgpu::Buffer,
+    width: u32,
+    height: u32,
+    padded_bytes_per_row: u32,
+}
+
+impl Headless {
+    /// Initialises an offscreen renderer for the supplied background
+    ///
+    /// Unless `adapter` names one, prefers a hardware adapter but falls back to the software
+    /// adapter, so no display or GPU is required.
+    ///
+    /// # Example
+    /// ```ignore
+    /// let mut headless = Headless::new(
+    ///     &Animation::still(image),
+    ///     WaterParams::default(),
+    ///     1920,
+    ///     1080,
+    ///     &AdapterOptions::default(),
+    /// )
+    /// .await?;
+    /// ```
+    pub async fn new(
+        animation: &Animation,
+        params: WaterParams,
+        width: u32,
+        height: u32,
+        adapter: &AdapterOptions,
+    ) -> Result<Self> {
+        ensure!(width > 0 && height > 0, "Output size must be non-zero");
+
+        let instance = wgpu::Instance::new(adapter.backend.backends());
+        let adapter = adapter.select(&instance, None).await?;
+        let (device, queue) = adapter::request_device(&adapter).await?;
+
+        let background = defs::Background::new(animation, &device, &queue, FORMAT, params.horizon)?;
+
+        let target = RenderTarget::new(&device, width, height, FORMAT);
+        // Same role as the scene target in State::render, but the size never changes
+        let scene = RenderTarget::new(&device, width, height, FORMAT);
+        let mut water = WaterRenderer::new(&device, FORMAT, &scene.view, params)?;
+        water.set_horizon(&queue, background.screen_horizon());
+        water.set_size(width, height);
+        if adapter::is_software(&adapter) {
+            water.limit_simulation(FALLBACK_RESOLUTION);
+        }
+
+        // Rows copied out of a texture have to be padded to a multiple of 256 bytes
+        let unpadded_bytes_per_row = 4 * width;
+        let align = wgpu::COPY_BYTES_PER_ROW_ALIGNMENT;
+        let padded_bytes_per_row = unpadded_bytes_per_row.div_ceil(align) * align;
+        let readback = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Readback buffer"),
+            size: (padded_bytes_per_row * height) as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::MAP_READ,
+            mapped_at_creation: false,
+        });
+
+        Ok(Headless {
+            device,
+            queue,
+            background,
+            water,
+            target,
+            scene,
+            readback,
+            width,
+            height,
+            padded_bytes_per_row,
+        })
+    }
+
+    /// Changes the look of the water for the following frames
+    ///
+    /// Fails if the simulation is turned on for the first time and its pipelines can't be built.
+    pub fn set_params(&mut self, params: WaterParams) -> Result<()> {
+        self.water.set_params(&self.device, params)?;
+        let screen_horizon = self.background.set_horizon(&self.queue, params.horizon);
+        self.water.set_horizon(&self.queue, screen_horizon);
+        Ok(())
+    }
+
+    /// Starts a ripple at `position`, a fraction of the output's width and height, `seconds`
+    /// into the animation. Returns false if `position` is above the water
+    pub fn add_ripple(&mut self, position: [f32; 2], seconds: f32) -> bool {
+        self.water.add_ripple(position, seconds)
+    }
+
+    /// Swaps the background, keeping the device and output size so many images can be rendered
+    /// without setting up the gpu again
+    ///
+    /// # Example
+    /// ```ignore
+    /// headless.set_background(&Animation::load(Path::new("next.png"), 12.0)?)?;
+    /// ```
+    pub fn set_background(&mut self, animation: &Animation) -> Result<()> {
+        self.background
+            .set_animation(&self.device, &self.queue, animation)?;
+        self.water
+            .set_horizon(&self.queue, self.background.screen_horizon());
+        Ok(())
+    }
+
+    /// Renders the frame `seconds` into the animation and reads it back from the gpu
+    ///
+    /// # Example
+    /// ```ignore
+    /// let frame = headless.render(2.0)?;
+    /// frame.save("frame.png")?;
+    /// ```
+    pub fn render(&mut self, seconds: f32) -> Result<image::RgbaImage> {
+        let mut encoder = self
+            .device
+            .create_command_encoder(&wgpu::CommandEncoderDescriptor {
+                label: Some("Offscreen encoder"),
+            });
+        self.background
+            .draw(&mut encoder, &self.scene.view, seconds);
+        self.background
+            .draw(&mut encoder, &self.target.view, seconds);
+        self.water.encode(
+            &self.device,
+            &self.queue,
+            &mut encoder,
+            &self.target.view,
+            seconds,
+        );
+
+        encoder.copy_texture_to_buffer(
+            wgpu::ImageCopyTexture {
+                aspect: wgpu::TextureAspect::All,
+                texture: &self.target.texture,
+                mip_level: 0,
+                origin: wgpu::Origin3d::ZERO,
+            },
+            wgpu::ImageCopyBuffer {
+                buffer: &self.readback,
+                layout: wgpu::ImageDataLayout {
+                    offset: 0,
+                    bytes_per_row: std::num::NonZeroU32::new(self.padded_bytes_per_row),
+                    rows_per_image: std::num::NonZeroU32::new(self.height),
+                },
+            },
+            wgpu::Extent3d {
+                width: self.width,
+                height: self.height,
+                depth_or_array_layers: 1,
+            },
+        );
+        self.queue.submit(std::iter::once(encoder.finish()));
+
+        // Block until the copy has finished and the buffer is mapped
+        let slice = self.readback.slice(..);
+        let (sender, receiver) = std::sync::mpsc::channel();
+        slice.map_async(wgpu::MapMode::Read, move |result| {
+            let _ = sender.send(result);
+        });
+        self.device.poll(wgpu::Maintain::Wait);
+        receiver.recv()??;
+
+        // Strip the row padding
+        let unpadded_bytes_per_row = (4 * self.width) as usize;
+        let mut pixels = Vec::with_capacity(unpadded_bytes_per_row * self.height as usize);
+        {
+            let data = slice.get_mapped_range();
+            for row in data.chunks(self.padded_bytes_per_row as usize) {
+                pixels.extend_from_slice(&row[..unpadded_bytes_per_row]);
+            }
+        }
+        self.readback.unmap();
+
+        image::RgbaImage::from_raw(self.width, self.height, pixels)
+            .context("Read back buffer does not match the output size")
+    }
+}
+
+// The gpu renderer when there is any adapter, otherwise the cpu one
+enum Renderer {
+    Gpu(Box<Headless>),
+    Cpu(Box<CpuRenderer>),
+}
+
+impl Renderer {
+    async fn new(
+        animation: &Animation,
+        params: WaterParams,
+        width: u32,
+        height: u32,
+        adapter: &AdapterOptions,
+    ) -> Result<Self> {
+        match Headless::new(animation, params, width, height, adapter).await {
+            Result::Ok(headless) => Ok(Renderer::Gpu(Box::new(headless))),
+            // Only when there is no adapter at all, picking one that doesn't exist is a mistake
+            Err(e) if matches!(e.downcast_ref(), Some(WaterError::NoAdapter { .. })) => {
+                log::warn!("{}, rendering on the cpu instead", e);
+                if params.simulation.is_some() {
+                    log::warn!("The cpu renderer leaves out the simulated water");
+                }
+                Ok(Renderer::Cpu(Box::new(CpuRenderer::new(
+                    animation, params, width, height,
+                )?)))
+            }
+            Err(e) => Err(e),
+        }
+    }
+
+    fn render(&mut self, seconds: f32) -> Result<image::RgbaImage> {
+        match self {
+            Renderer::Gpu(headless) => headless.render(seconds),
+            Renderer::Cpu(cpu) => Ok(cpu.render(seconds)),
+        }
+    }
+}
+
+/// Renders `frames` frames of the background without a window and saves them as PNGs
+///
+/// Falls back to rendering on the cpu, without the simulated water, when there is no graphics
+/// adapter at all. Frames are spaced exactly `1 / fps` seconds apart, however long they take to
+/// render, and written to `output_dir` as `frame_0000.png`, `frame_0001.png`, ...
+///
+/// # Example
+/// ```ignore
+/// run_headless(&source, params, Path::new("frames"), 60, 30.0, 1920, 1080, &adapter).await?;
+/// ```
+#[allow(clippy::too_many_arguments)]
+pub async fn run_headless(
+    source: &BackgroundSource,
+    params: WaterParams,
+    output_dir: &Path,
+    frames: u32,
+    fps: f32,
+    width: u32,
+    height: u32,
+    adapter: &AdapterOptions,
+) -> Result<()> {
+    // Without a logger wgpu errors are not useful
+    crate::init_logging();
+    ensure!(fps > 0.0, "Frame rate must be positive");
+
+    let animation = source.load()?;
+    let mut headless = Renderer::new(&animation, params, width, height, adapter).await?;
+
+    std::fs::create_dir_all(output_dir)
+        .with_context(|| format!("Failed to create {}", output_dir.display()))?;
+    let mut clock = Clock::fixed_step(1.0 / fps);
+    for frame in 0..frames {
+        let path = output_dir.join(format!("frame_{:04}.png", frame));
+        headless
+            .render(clock.seconds())?
+            .save(&path)
+            .with_context(|| format!("Failed to write {}", path.display()))?;
+        clock.tick();
+    }
+
+    Ok(())
+}
+
+/// Runs the water effect once over the background and saves the result to `output`
+///
+/// The output size defaults to the size of the background image. Falls back to rendering on the
+/// cpu when there is no graphics adapter at all.
+///
+/// # Example
+/// ```ignore
+/// render_image(&source, params, Path::new("out.png"), 3.5, None, None, &adapter).await?;
+/// ```
+pub async fn render_image(
+    source: &BackgroundSource,
+    params: WaterParams,
+    output: &Path,
+    seconds: f32,
+    width: Option<u32>,
+    height: Option<u32>,
+    adapter: &AdapterOptions,
+) -> Result<()> {
+    // Without a logger wgpu errors are not useful
+    crate::init_logging();
+
+    let animation = source.load()?;
+    let first = &animation.frames[0].image;
+    let width = width.unwrap_or_else(|| first.width());
+    let height = height.unwrap_or_else(|| first.height());
+    let mut headless = Renderer::new(&animation, params, width, height, adapter).await?;
+
+    headless
+        .render(seconds)?
+        .save(output)
+        .with_context(|| format!("Failed to write {}", output.display()))?;
+
+    Ok(())
+}
@@ -1,5 +1,141 @@
+use clap::{Parser, Subcommand};
+use std::path::PathBuf;
+#[cfg(feature = "viewer")]
 use wgpu_assignment::run;
+use wgpu_assignment::{
+    list_adapters, render_image, run_headless, AdapterChoice, AdapterOptions, Backend,
+    BackgroundSource, Playback, WaterParams,
+};
 
-fn main() {
-    pollster::block_on(run());
+#[derive(Parser)]
+#[command(about = "Reflective water shader")]
+struct Args {
+    /// Image, animated GIF or PNG, or directory of numbered frames to use as the background
+    #[arg(long, global = true)]
+    background: Option<PathBuf>,
+    /// What animated backgrounds do after their last frame: loop or ping-pong
+    #[arg(long, global = true, default_value = "loop")]
+    playback: Playback,
+    /// Frame rate of backgrounds loaded from a directory of frames
+    #[arg(long, global = true, default_value_t = 12.0)]
+    sequence_fps: f32,
+    /// JSON file of water parameters. The window reloads it whenever it changes
+    #[arg(long, global = true)]
+    params: Option<PathBuf>,
+    /// Graphics API to use: all, vulkan, gl, metal, dx12 or dx11
+    #[arg(long, global = true, default_value = "all")]
+    backend: Backend,
+    /// Name or index of the adapter to use, as printed by --list-adapters. By default the best
+    /// hardware adapter is picked, falling back to a software one
+    #[arg(long, global = true)]
+    adapter: Option<AdapterChoice>,
+    /// Print the available adapters and exit
+    #[arg(long)]
+    list_adapters: bool,
+    #[command(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Render frames without a window and save them as PNGs
+    Headless {
+        /// Directory the frames are written to
+        #[arg(long, default_value = "frames")]
+        output_dir: PathBuf,
+        /// Number of frames to render
+        #[arg(long, default_value_t = 1)]
+        frames: u32,
+        /// Frames per second of animation time
+        #[arg(long, default_value_t = 30.0)]
+        fps: f32,
+        #[arg(long, default_value_t = 1280)]
+        width: u32,
+        #[arg(long, default_value_t = 720)]
+        height: u32,
+    },
+    /// Apply the water effect to a single image
+    Render {
+        /// Image or animation to use as the background
+        #[arg(long)]
+        input: PathBuf,
+        /// Where to save the rendered image
+        #[arg(long)]
+        output: PathBuf,
+        /// Point in the animation to render, in seconds
+        #[arg(long, default_value_t = 0.0)]
+        time: f32,
+        /// Output width, defaults to the input width
+        #[arg(long)]
+        width: Option<u32>,
+        /// Output height, defaults to the input height
+        #[arg(long)]
+        height: Option<u32>,
+    },
+}
+
+fn main() -> anyhow::Result<()> {
+    let args = Args::parse();
+    if args.list_adapters {
+        for adapter in list_adapters(args.backend) {
+            println!("{}", adapter);
+        }
+        return Ok(());
+    }
+
+    let adapter = AdapterOptions {
+        backend: args.backend,
+        choice: args.adapter,
+        ..AdapterOptions::default()
+    };
+    let source = BackgroundSource {
+        path: args.background,
+        playback: args.playback,
+        sequence_fps: args.sequence_fps,
+    };
+    let params = match &args.params {
+        Some(path) if args.command.is_some() => WaterParams::load(path)?,
+        _ => WaterParams::default(),
+    };
+    match args.command {
+        #[cfg(feature = "viewer")]
+        None => pollster::block_on(run(source, args.params, adapter))?,
+        #[cfg(not(feature = "viewer"))]
+        None => {
+            anyhow::bail!("Built without the viewer feature, use the headless or render command")
+        }
+        Some(Command::Headless {
+            output_dir,
+            frames,
+            fps,
+            width,
+            height,
+        }) => pollster::block_on(run_headless(
+            &source,
+            params,
+            &output_dir,
+            frames,
+            fps,
+            width,
+            height,
+            &adapter,
+        ))?,
+        Some(Command::Render {
+            input,
+            output,
+            time,
+            width,
+            height,
+        }) => {
+            let source = BackgroundSource {
+                path: Some(input),
+                ..source
+            };
+            pollster::block_on(render_image(
+                &source, params, &output, time, width, height, &adapter,
+            ))?
+        }
+    }
+
+    Ok(())
 }
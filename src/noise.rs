@@ -0,0 +1,383 @@
+//! CPU versions of the noise and distortion in `shader.wgsl`
+//!
+//! Each function follows the shader operation for operation in `f32`, so for the same inputs it
+//! gives the same result as the GPU, up to the precision of the driver's `sin`. Useful for
+//! checking rendered output, or for knowing how far the water is displaced at a point.
+
+use crate::params::{NoiseHash, NoiseKind, NoiseParams, WaterParams};
+
+// WGSL's fract, which is defined as x - floor(x) rather than Rust's x - trunc(x)
+fn fract(x: f32) -> f32 {
+    x - x.floor()
+}
+
+// WGSL's mix
+fn mix(a: f32, b: f32, t: f32) -> f32 {
+    a * (1.0 - t) + b * t
+}
+
+fn dot(a: [f32; 2], b: [f32; 2]) -> f32 {
+    a[0] * b[0] + a[1] * b[1]
+}
+
+// The pcg2d integer hash from "Hash Functions for GPU Rendering", Jarzynski and Olano
+fn pcg2d(v: [u32; 2]) -> [u32; 2] {
+    let mut h = v.map(|v| v.wrapping_mul(1664525).wrapping_add(1013904223));
+    h[0] = h[0].wrapping_add(h[1].wrapping_mul(1664525));
+    h[1] = h[1].wrapping_add(h[0].wrapping_mul(1664525));
+    h = h.map(|h| h ^ (h >> 16));
+    h[0] = h[0].wrapping_add(h[1].wrapping_mul(1664525));
+    h[1] = h[1].wrapping_add(h[0].wrapping_mul(1664525));
+    h.map(|h| h ^ (h >> 16))
+}
+
+/// Pseudo random gradient for a lattice point, each component in `-1.0..1.0`
+///
+/// Matches `rand` in the shader. The pcg hash only looks at the whole part of `tex_coords`,
+/// wrapped to [`NoiseParams::PCG_PERIOD`], and gives exactly the same result as the gpu.
+pub fn rand(hash: NoiseHash, tex_coords: [f32; 2]) -> [f32; 2] {
+    match hash {
+        NoiseHash::Sine => {
+            let t = [
+                dot(tex_coords, [127.1, 311.7]),
+                dot(tex_coords, [269.5, 183.3]),
+            ];
+            t.map(|t| -1.0 + 2.0 * fract(t.sin() * 43758.547))
+        }
+        NoiseHash::Pcg => {
+            let period = NoiseParams::PCG_PERIOD as u32;
+            let cell = tex_coords.map(|c| c as i32 as u32 & (period - 1));
+            pcg2d(cell).map(|h| -1.0 + 2.0 * ((h >> 8) as f32 / 16777216.0))
+        }
+    }
+}
+
+/// Gradient noise, smooth and roughly in `-0.7..0.7`
+///
+/// Matches `noise` in the shader.
+///
+/// # Example
+/// ```ignore
+/// let height = noise::noise(NoiseHash::Pcg, [x * 4.0, y * 4.0]);
+/// ```
+pub fn noise(hash: NoiseHash, tex_coords: [f32; 2]) -> f32 {
+    let i = tex_coords.map(f32::floor);
+    let f = tex_coords.map(fract);
+
+    let u = f.map(|f| f * f * (3.0 - 2.0 * f));
+
+    let corner = |x: f32, y: f32| dot(rand(hash, [i[0] + x, i[1] + y]), [f[0] - x, f[1] - y]);
+    mix(
+        mix(corner(0.0, 0.0), corner(1.0, 0.0), u[0]),
+        mix(corner(0.0, 1.0), corner(1.0, 1.0), u[0]),
+        u[1],
+    )
+}
+
+/// Simplex noise over the same gradients as [`noise`], roughly in `-1.0..1.0`
+///
+/// Matches `simplex` in the shader.
+pub fn simplex(hash: NoiseHash, tex_coords: [f32; 2]) -> f32 {
+    // Skews the triangle grid onto squares and back
+    const K1: f32 = 0.366_025_42; // (sqrt(3) - 1) / 2
+    const K2: f32 = 0.211_324_87; // (3 - sqrt(3)) / 6
+
+    let skew = (tex_coords[0] + tex_coords[1]) * K1;
+    let i = tex_coords.map(|c| (c + skew).floor());
+    let unskew = (i[0] + i[1]) * K2;
+    let a = [0, 1].map(|n| tex_coords[n] - i[n] + unskew);
+    let o = if a[0] > a[1] { [1.0, 0.0] } else { [0.0, 1.0] };
+    let b = [0, 1].map(|n| a[n] - o[n] + K2);
+    let c = a.map(|a| a - 1.0 + 2.0 * K2);
+
+    let corner = |d: [f32; 2], gradient: [f32; 2]| {
+        let h = (0.5 - dot(d, d)).max(0.0);
+        h * h * h * h * dot(d, rand(hash, gradient))
+    };
+    let n = [
+        corner(a, i),
+        corner(b, [i[0] + o[0], i[1] + o[1]]),
+        corner(c, i.map(|i| i + 1.0)),
+    ];
+    n[0] * 70.0 + n[1] * 70.0 + n[2] * 70.0
+}
+
+/// Distance to the nearest of one random point per grid cell, less a half so it is roughly
+/// centred on zero
+///
+/// Matches `worley` in the shader.
+pub fn worley(hash: NoiseHash, tex_coords: [f32; 2]) -> f32 {
+    let i = tex_coords.map(f32::floor);
+    let f = tex_coords.map(fract);
+
+    let mut nearest = 8.0f32;
+    for y in -1..=1 {
+        for x in -1..=1 {
+            let cell = [x as f32, y as f32];
+            let offset = rand(hash, [i[0] + cell[0], i[1] + cell[1]]);
+            let feature = [0, 1].map(|n| cell[n] + 0.5 + 0.5 * offset[n]);
+            let d = [feature[0] - f[0], feature[1] - f[1]];
+            nearest = nearest.min(dot(d, d).sqrt());
+        }
+    }
+    nearest - 0.5
+}
+
+fn base_noise(params: &NoiseParams, tex_coords: [f32; 2]) -> f32 {
+    match params.kind {
+        NoiseKind::Gradient => noise(params.hash, tex_coords),
+        NoiseKind::Simplex => simplex(params.hash, tex_coords),
+        NoiseKind::Worley => worley(params.hash, tex_coords),
+    }
+}
+
+/// Octaves of the noise picked by `params` added together, divided by their total strength so
+/// the range doesn't depend on the number of octaves
+///
+/// Matches `fbm` in the shader.
+pub fn fbm(params: &NoiseParams, tex_coords: [f32; 2]) -> f32 {
+    let mut sum = 0.0;
+    let mut total = 0.0;
+    let mut strength = 1.0;
+    let mut frequency = 1.0;
+    for _ in 0..params.octaves.clamp(1, NoiseParams::MAX_OCTAVES) {
+        sum += strength * base_noise(params, tex_coords.map(|c| c * frequency));
+        total += strength;
+        strength *= params.gain;
+        frequency *= params.lacunarity;
+    }
+    sum / total
+}
+
+/// The noise the water is distorted by, including the domain warp
+///
+/// Matches `water_noise` in the shader.
+///
+/// # Example
+/// ```ignore
+/// let height = water_noise(&params.noise, [x * 4.0, y * 4.0]);
+/// ```
+pub fn water_noise(params: &NoiseParams, tex_coords: [f32; 2]) -> f32 {
+    if params.warp > 0.0 {
+        // Two more lookups, far enough apart to be unrelated
+        let offset = [
+            fbm(params, tex_coords),
+            fbm(params, [tex_coords[0] + 5.2, tex_coords[1] + 1.3]),
+        ];
+        return fbm(
+            params,
+            [0, 1].map(|n| tex_coords[n] + params.warp * offset[n]),
+        );
+    }
+    fbm(params, tex_coords)
+}
+
+/// Distance, in texture coordinates, the water at `tex_coords` samples away from its mirror
+/// image `seconds` into the animation
+///
+/// `horizon` is the fraction of the screen above the water, as passed to
+/// [`WaterRenderer::set_horizon`](crate::WaterRenderer::set_horizon). Without
+/// [`WaterParams::wind`] both axes are offset the same.
+///
+/// Matches `water_offset` in the shader.
+pub fn water_offset(
+    params: &WaterParams,
+    horizon: f32,
+    tex_coords: [f32; 2],
+    seconds: f32,
+) -> [f32; 2] {
+    let falloff = ((tex_coords[1] - horizon) * -params.falloff_rate)
+        .clamp(params.falloff_min, params.falloff_max);
+    let Some(wind) = params.wind else {
+        let time = params.noise.wrap(seconds);
+        let coords = tex_coords.map(|c| c * params.noise_scale + time);
+        let random =
+            (water_noise(&params.noise, coords) + params.bias) * params.amplitude * falloff;
+        return [random, random];
+    };
+
+    // In the wind's frame, x along the wind and y across it
+    let along = wind.direction_vector();
+    let across = [-along[1], along[0]];
+    let scaled = tex_coords.map(|c| c * params.noise_scale);
+    let drift = params.noise.wrap(wind.drift(seconds));
+    let coords = [
+        dot(scaled, along) - drift,
+        dot(scaled, across) / wind.anisotropy,
+    ];
+
+    let push = water_noise(&params.noise, coords);
+    let sway = water_noise(&params.noise, [coords[0] + 31.0, coords[1] + 17.0]) / wind.anisotropy;
+    let gust = wind.gust(seconds);
+    [0, 1].map(|n| {
+        let offset = along[n] * push + across[n] * sway + params.bias;
+        offset * params.amplitude * gust * falloff
+    })
+}
+
+/// Point of the scene the water at `tex_coords` shows, after the offset and clamping in
+/// `fs_main_water`
+///
+/// # Example
+/// ```ignore
+/// let [u, v] = water_sample_coords(&params, 0.5, [0.25, 0.2], clock.seconds());
+/// let pixel = scene.get_pixel((u * width as f32) as u32, (v * height as f32) as u32);
+/// ```
+pub fn water_sample_coords(
+    params: &WaterParams,
+    horizon: f32,
+    tex_coords: [f32; 2],
+    seconds: f32,
+) -> [f32; 2] {
+    let offset = water_offset(params, horizon, tex_coords, seconds);
+    [
+        (tex_coords[0] + offset[0]).clamp(0.0, 1.0),
+        (tex_coords[1] + offset[1]).clamp(0.0, horizon),
+    ]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::params::Wind;
+
+    const HASHES: [NoiseHash; 2] = [NoiseHash::Sine, NoiseHash::Pcg];
+
+    #[test]
+    fn noise_is_zero_on_the_lattice() {
+        for hash in HASHES {
+            for point in [[0.0, 0.0], [3.0, -2.0], [17.0, 5.0]] {
+                assert_eq!(noise(hash, point), 0.0);
+            }
+        }
+    }
+
+    #[test]
+    fn noise_is_continuous_across_cells() {
+        for hash in HASHES {
+            let before = noise(hash, [2.0 - 1e-4, 0.5]);
+            let after = noise(hash, [2.0 + 1e-4, 0.5]);
+            assert!((before - after).abs() < 1e-3);
+        }
+    }
+
+    #[test]
+    fn one_octave_is_the_plain_noise() {
+        let params = NoiseParams::default();
+        for point in [[0.3, 0.7], [-4.2, 9.9]] {
+            assert_eq!(water_noise(&params, point), noise(params.hash, point));
+        }
+    }
+
+    #[test]
+    fn pcg_noise_repeats_every_period() {
+        let period = NoiseParams::PCG_PERIOD;
+        for kind in [NoiseKind::Gradient, NoiseKind::Worley] {
+            let params = NoiseParams {
+                kind,
+                hash: NoiseHash::Pcg,
+                octaves: 3,
+                warp: 0.5,
+                ..NoiseParams::default()
+            };
+            for point in [[0.3, 0.7], [-4.2, 9.9], [100.1, -37.6]] {
+                let next = point.map(|c| c + period);
+                let difference = water_noise(&params, point) - water_noise(&params, next);
+                assert!(difference.abs() < 1e-3, "{:?} at {:?}", kind, point);
+            }
+        }
+    }
+
+    #[test]
+    fn pcg_time_wraps_without_a_jump() {
+        let params = WaterParams {
+            noise: NoiseParams {
+                hash: NoiseHash::Pcg,
+                ..NoiseParams::default()
+            },
+            ..WaterParams::default()
+        };
+        let period = NoiseParams::PCG_PERIOD;
+        let before = water_offset(&params, 0.5, [0.3, 0.2], period - 0.001);
+        let after = water_offset(&params, 0.5, [0.3, 0.2], period + 0.001);
+        assert!((before[0] - after[0]).abs() < 1e-4);
+        // Long after the start the time is still small
+        assert!(params.noise.wrap(86400.0 * 30.0) < period);
+    }
+
+    #[test]
+    fn strong_anisotropy_pushes_along_the_wind() {
+        let wind = Wind {
+            direction: 30.0,
+            anisotropy: 1000.0,
+            ..Wind::default()
+        };
+        let params = WaterParams {
+            bias: 0.0,
+            wind: Some(wind),
+            ..WaterParams::default()
+        };
+        let [x, y] = wind.direction_vector();
+        for n in 0..20 {
+            let offset = water_offset(&params, 0.5, [n as f32 * 0.05, 0.1], 3.0);
+            // Cross product with the wind, zero when parallel
+            assert!((offset[0] * y - offset[1] * x).abs() < 1e-5);
+        }
+    }
+
+    #[test]
+    fn gusts_keep_the_drift_smooth() {
+        let wind = Wind {
+            gust_strength: 1.0,
+            ..Wind::default()
+        };
+        for n in 0..100 {
+            let t = n as f32 * 0.37;
+            let speed = (wind.drift(t + 0.01) - wind.drift(t)) / 0.01;
+            // The drift moves at the wind's speed scaled by the gust
+            assert!((speed - wind.speed * wind.gust(t + 0.005)).abs() < 1e-2);
+            assert!(wind.gust(t) >= 0.0);
+        }
+    }
+
+    #[test]
+    fn every_kind_stays_in_range() {
+        for kind in [NoiseKind::Gradient, NoiseKind::Simplex, NoiseKind::Worley] {
+            let params = NoiseParams {
+                kind,
+                octaves: 5,
+                warp: 1.5,
+                ..NoiseParams::default()
+            };
+            for n in 0..500 {
+                let point = [n as f32 * 0.137, n as f32 * 0.071 - 10.0];
+                let value = water_noise(&params, point);
+                assert!(
+                    value.abs() <= 1.0,
+                    "{:?} gave {} at {:?}",
+                    kind,
+                    value,
+                    point
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn fract_matches_wgsl_for_negative_numbers() {
+        assert_eq!(fract(-0.25), 0.75);
+    }
+
+    #[test]
+    fn sample_stays_above_the_horizon() {
+        let params = WaterParams {
+            amplitude: 1.0,
+            ..WaterParams::default()
+        };
+        for y in 0..20 {
+            let [u, v] = water_sample_coords(&params, 0.4, [0.5, y as f32 * 0.05 - 0.5], 1.0);
+            assert!((0.0..=1.0).contains(&u));
+            assert!((0.0..=0.4).contains(&v));
+        }
+    }
+}
@@ -0,0 +1,372 @@
+use anyhow::*;
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+/// Values that define the look of the water
+///
+/// Fields missing from a serialized copy keep their default value, so a file only needs to list
+/// what it changes.
+#[derive(Copy, Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct WaterParams {
+    /// Strength of the distortion, in texture coordinates
+    pub amplitude: f32,
+    /// Added to the noise before scaling, shifts the distortion in one direction
+    pub bias: f32,
+    /// Frequency of the noise across the water
+    pub noise_scale: f32,
+    /// How quickly the distortion grows with distance from the horizon
+    pub falloff_rate: f32,
+    /// Smallest fraction of the distortion applied, right at the horizon
+    pub falloff_min: f32,
+    /// Largest fraction of the distortion applied, far from the horizon
+    pub falloff_max: f32,
+    /// Where the water meets the background
+    pub horizon: Horizon,
+    /// Shape of the ripples
+    pub noise: NoiseParams,
+    /// Wind pushing the ripples. Without it they drift diagonally and distort both axes equally
+    pub wind: Option<Wind>,
+    /// Rings spreading from clicks on the water
+    pub ripples: RippleParams,
+    /// Waves simulated on the gpu on top of the noise. Clicks disturb the simulated water instead
+    /// of starting [`RippleParams`] rings
+    pub simulation: Option<SimulationParams>,
+}
+
+impl Default for WaterParams {
+    fn default() -> Self {
+        WaterParams {
+            amplitude: 0.01,
+            bias: -0.5,
+            noise_scale: 1.0,
+            falloff_rate: 3.0,
+            falloff_min: 0.3,
+            falloff_max: 1.0,
+            horizon: Horizon::default(),
+            noise: NoiseParams::default(),
+            wind: None,
+            ripples: RippleParams::default(),
+            simulation: None,
+        }
+    }
+}
+
+/// Look of the rings that spread out from clicks on the water
+///
+/// Distances are in screen heights, so the rings look the same at any window size.
+#[derive(Copy, Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct RippleParams {
+    /// How fast the rings spread, in screen heights per second
+    pub speed: f32,
+    /// Distance between the crests of a ring
+    pub wavelength: f32,
+    /// How quickly the rings die away. After `t` seconds their strength is scaled by
+    /// `exp(-damping * t)`
+    pub damping: f32,
+    /// How far the crests push the reflection, in texture coordinates
+    pub strength: f32,
+}
+
+impl Default for RippleParams {
+    fn default() -> Self {
+        RippleParams {
+            speed: 0.3,
+            wavelength: 0.03,
+            damping: 1.5,
+            strength: 0.01,
+        }
+    }
+}
+
+/// Heightfield of the water stepped by the wave equation, so disturbances spread, bounce off the
+/// edges of the screen and die away
+///
+/// Serialized as `{"resolution": 128, "rain": 2.0}`, where missing fields keep their default.
+/// Distances are in screen heights.
+#[derive(Copy, Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SimulationParams {
+    /// Number of cells down the water. Across it there are this many times the width of the
+    /// screen over its height. Limited on software adapters, see
+    /// [`FALLBACK_RESOLUTION`](crate::FALLBACK_RESOLUTION), and to the largest grid the device can
+    /// hold
+    pub resolution: u32,
+    /// How fast waves travel, in screen heights per second
+    pub speed: f32,
+    /// How quickly the waves die away. After `t` seconds their height is scaled by
+    /// `exp(-damping * t)`
+    pub damping: f32,
+    /// How far the slope of the water bends the reflection, in texture coordinates
+    pub strength: f32,
+    /// Drops of rain landing on the water each second
+    pub rain: f32,
+    /// Radius of the dent a click or raindrop makes, which is as deep as it is wide
+    pub drop_size: f32,
+}
+
+impl Default for SimulationParams {
+    fn default() -> Self {
+        SimulationParams {
+            resolution: 128,
+            speed: 0.2,
+            damping: 0.5,
+            strength: 0.05,
+            rain: 0.0,
+            drop_size: 0.03,
+        }
+    }
+}
+
+/// Wind blowing across the water, which moves and stretches the ripples
+///
+/// Serialized as `{"direction": 30.0, "speed": 0.8}`, where missing fields keep their default.
+#[derive(Copy, Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Wind {
+    /// Direction the wind blows towards, in degrees anticlockwise from the right. 90 blows away
+    /// from the viewer, towards the horizon
+    pub direction: f32,
+    /// How fast the ripples drift with the wind, in cells of the noise per second
+    pub speed: f32,
+    /// How many times longer the ripples are across the wind than along it, and how much less they
+    /// distort across it. 1 gives round ripples
+    pub anisotropy: f32,
+    /// How much gusts change the speed of the wind and the strength of the ripples, from 0 for a
+    /// steady wind to 1 for gusts that die down completely
+    pub gust_strength: f32,
+    /// Average number of seconds from one gust to the next
+    pub gust_period: f32,
+}
+
+impl Default for Wind {
+    fn default() -> Self {
+        Wind {
+            direction: 0.0,
+            speed: 0.5,
+            anisotropy: 3.0,
+            gust_strength: 0.3,
+            gust_period: 5.0,
+        }
+    }
+}
+
+// Gusts are two waves at unrelated frequencies, so they don't visibly repeat
+const GUSTS: [(f64, f64, f64); 2] = [(0.6, 1.0, 0.0), (0.4, 2.3, 1.7)];
+
+impl Wind {
+    /// Unit vector the wind blows along, in the water's texture coordinates
+    pub fn direction_vector(&self) -> [f32; 2] {
+        let radians = self.direction.to_radians();
+        [radians.cos(), radians.sin()]
+    }
+
+    /// Strength of the wind `seconds` in, relative to its average
+    pub fn gust(&self, seconds: f32) -> f32 {
+        if self.gust_period <= 0.0 {
+            return 1.0;
+        }
+        let omega = std::f64::consts::TAU / self.gust_period as f64;
+        let t = seconds as f64;
+        let wave: f64 = GUSTS
+            .iter()
+            .map(|(weight, frequency, phase)| weight * (frequency * omega * t + phase).sin())
+            .sum();
+        (1.0 + self.gust_strength.clamp(0.0, 1.0) as f64 * wave) as f32
+    }
+
+    /// How far the ripples have drifted along the wind after `seconds`, including the gusts
+    pub fn drift(&self, seconds: f32) -> f32 {
+        let t = seconds as f64;
+        if self.gust_period <= 0.0 {
+            return (self.speed as f64 * t) as f32;
+        }
+        // The integral of the speed, which is scaled by gust()
+        let omega = std::f64::consts::TAU / self.gust_period as f64;
+        let wave: f64 = GUSTS
+            .iter()
+            .map(|(weight, frequency, phase)| {
+                -weight / (frequency * omega) * (frequency * omega * t + phase).cos()
+            })
+            .sum();
+        let strength = self.gust_strength.clamp(0.0, 1.0) as f64;
+        (self.speed as f64 * (t + strength * wave)) as f32
+    }
+}
+
+/// Noise the ripples are made from, and how it is layered
+///
+/// Serialized as `{"kind": "simplex", "octaves": 4}`, where missing fields keep their default. The
+/// defaults give a single layer of gradient noise.
+#[derive(Copy, Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct NoiseParams {
+    pub kind: NoiseKind,
+    pub hash: NoiseHash,
+    /// Number of layers of noise added together, as fractal Brownian motion. More layers add
+    /// finer detail, up to [`NoiseParams::MAX_OCTAVES`]
+    pub octaves: u32,
+    /// How much the frequency grows from one layer to the next
+    pub lacunarity: f32,
+    /// How much the strength shrinks from one layer to the next
+    pub gain: f32,
+    /// How far the noise is pushed around by more noise before it is sampled. Above zero it
+    /// swirls the ripples, larger values give more turbulent water
+    pub warp: f32,
+}
+
+impl NoiseParams {
+    /// Largest number of octaves the shader adds up, more are ignored
+    pub const MAX_OCTAVES: u32 = 8;
+
+    /// Number of grid cells after which noise built on [`NoiseHash::Pcg`] repeats, and the number
+    /// of seconds after which its time wraps
+    pub const PCG_PERIOD: f32 = 256.0;
+
+    /// Wraps a distance the noise has moved, such as the time or the wind's drift
+    ///
+    /// With the pcg hash it wraps every [`NoiseParams::PCG_PERIOD`]. The noise repeats over the
+    /// same distance, so nothing jumps, and the distance stays small enough to keep its precision
+    /// however long the water runs.
+    pub fn wrap(&self, distance: f32) -> f32 {
+        match self.hash {
+            NoiseHash::Sine => distance,
+            NoiseHash::Pcg => distance.rem_euclid(Self::PCG_PERIOD),
+        }
+    }
+}
+
+impl Default for NoiseParams {
+    fn default() -> Self {
+        NoiseParams {
+            kind: NoiseKind::default(),
+            hash: NoiseHash::default(),
+            octaves: 1,
+            lacunarity: 2.0,
+            gain: 0.5,
+            warp: 0.0,
+        }
+    }
+}
+
+/// Hash turning grid cells into the random gradients and points the noise is built from
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum NoiseHash {
+    /// The classic `fract(sin(x) * 43758.5453)`. Drivers disagree on `sin` of large numbers, so
+    /// the ripples differ between adapters and turn blocky once the time grows large
+    #[default]
+    Sine,
+    /// The integer pcg2d hash, identical on every adapter. The noise repeats every
+    /// [`NoiseParams::PCG_PERIOD`] cells so the time can wrap without a jump, except for
+    /// [`NoiseKind::Simplex`] or a lacunarity that isn't a whole number, which jump once a period
+    Pcg,
+}
+
+/// Basic noise function the ripples are built from
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum NoiseKind {
+    /// Smooth gradient noise, gives soft rounded ripples
+    #[default]
+    Gradient,
+    /// Simplex noise, similar to gradient noise with fewer grid aligned artifacts
+    Simplex,
+    /// Distance to the nearest of a set of random points, gives a cellular pattern like light on a
+    /// pool floor
+    Worley,
+}
+
+/// Position of the line where the water meets the background
+///
+/// Serialized as `{"fraction": 0.5}` or `{"source_row": 310}`.
+#[derive(Copy, Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Horizon {
+    /// Fraction of the screen height from the top. The whole background is fitted above it
+    Fraction(f32),
+    /// Pixel row of the background image, counted from the top. The background is cropped at
+    /// this row and the horizon sits at the same relative height on screen
+    SourceRow(u32),
+}
+
+impl Default for Horizon {
+    fn default() -> Self {
+        Horizon::Fraction(0.5)
+    }
+}
+
+impl Horizon {
+    /// Returns the height of the horizon as a fraction of the screen, and the texture coordinate
+    /// the background is cropped at, for a background `source_height` pixels tall
+    pub fn resolve(self, source_height: u32) -> (f32, f32) {
+        match self {
+            Horizon::Fraction(fraction) => (fraction.clamp(0.0, 1.0), 1.0),
+            Horizon::SourceRow(row) => {
+                let v = (row as f32 / source_height.max(1) as f32).clamp(0.0, 1.0);
+                (v, v)
+            }
+        }
+    }
+}
+
+impl WaterParams {
+    /// Reads parameters from a JSON file
+    ///
+    /// # Example
+    /// ```ignore
+    /// let params = WaterParams::load(Path::new("pond.json"))?;
+    /// ```
+    pub fn load(path: &Path) -> Result<Self> {
+        let file = std::fs::File::open(path)
+            .with_context(|| format!("Failed to open {}", path.display()))?;
+        serde_json::from_reader(std::io::BufReader::new(file))
+            .with_context(|| format!("Failed to parse {}", path.display()))
+    }
+}
+
+/// Reloads parameters from a JSON file whenever it changes on disk
+pub struct ParamsWatcher {
+    path: PathBuf,
+    modified: Option<SystemTime>,
+    polled: bool,
+}
+
+impl ParamsWatcher {
+    pub fn new(path: PathBuf) -> Self {
+        ParamsWatcher {
+            path,
+            modified: None,
+            polled: false,
+        }
+    }
+
+    /// The file being watched
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Returns the parameters if the file changed since the last call. The first call always
+    /// loads them
+    ///
+    /// # Example
+    /// ```ignore
+    /// if let Some(params) = watcher.poll() {
+    ///     water.set_params(&device, params?)?;
+    /// }
+    /// ```
+    pub fn poll(&mut self) -> Option<Result<WaterParams>> {
+        let modified = std::fs::metadata(&self.path)
+            .and_then(|metadata| metadata.modified())
+            .ok();
+        if self.polled && modified == self.modified {
+            return None;
+        }
+
+        self.polled = true;
+        self.modified = modified;
+        Some(WaterParams::load(&self.path))
+    }
+}
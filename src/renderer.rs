@@ -0,0 +1,421 @@
+use crate::defs::{self, FragmentEntry, Pipelines, RippleUniforms, Vertex, WaterUniforms};
+use crate::error::WaterError;
+use crate::params::{Horizon, WaterParams};
+use crate::ripples::Ripples;
+use crate::simulation::Simulation;
+use wgpu::util::DeviceExt;
+
+/// Draws the water reflection of a scene texture into any wgpu render target
+///
+/// Only needs a device and queue, so the effect can be added to an existing renderer. The window
+/// and headless modes are both built on top of it.
+///
+/// # Example
+/// ```ignore
+/// let mut water =
+///     WaterRenderer::new(&device, config.format, &scene_view, WaterParams::default())?;
+///
+/// // Draw the scene into scene_view and the screen, then
+/// water.render(&device, &queue, &screen_view, seconds);
+/// ```
+pub struct WaterRenderer {
+    indices: wgpu::Buffer,
+    vertex: wgpu::Buffer,
+    pipelines: Pipelines,
+    texture_bind_group_layout: wgpu::BindGroupLayout,
+    sampler: wgpu::Sampler,
+    input_bind_group: wgpu::BindGroup,
+    params: WaterParams,
+    horizon: f32,
+    uniform_buffer: wgpu::Buffer,
+    ripples: Ripples,
+    ripple_buffer: wgpu::Buffer,
+    // Width of the target over its height, keeps the ripples round
+    aspect: f32,
+    uniform_bind_group_layout: wgpu::BindGroupLayout,
+    uniform_bind_group: wgpu::BindGroup,
+    // Built the first time the parameters ask for a simulation
+    simulation: Option<Simulated>,
+    simulation_limit: u32,
+}
+
+// The simulation and the water pipelines sampling its heightfield
+struct Simulated {
+    simulation: Simulation,
+    pipelines: Pipelines,
+}
+
+impl WaterRenderer {
+    /// Creates a renderer drawing into targets of `format`, reflecting the scene in `input`
+    ///
+    /// `input` must be a 2D view of a texture created with `TEXTURE_BINDING` usage, in the same
+    /// format as the targets or one with the same sRGB encoding. The water
+    /// starts at the horizon in `params` when that is a fraction of the screen, and halfway down
+    /// otherwise, as rows of a source image can only be placed by the caller through
+    /// [`WaterRenderer::set_horizon`].
+    pub fn new(
+        device: &wgpu::Device,
+        format: wgpu::TextureFormat,
+        input: &wgpu::TextureView,
+        params: WaterParams,
+    ) -> Result<Self, WaterError> {
+        let horizon = match params.horizon {
+            Horizon::Fraction(fraction) => fraction.clamp(0.0, 1.0),
+            Horizon::SourceRow(_) => 0.5,
+        };
+        let vertices = Self::vertices(horizon);
+
+        let vertex_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Vertex Buffer"),
+            contents: bytemuck::cast_slice(&vertices),
+            usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST,
+        });
+
+        let indices: &[u16] = &[2, 1, 0, 2, 3, 1];
+        let index_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Index Buffer"),
+            contents: bytemuck::cast_slice(indices),
+            usage: wgpu::BufferUsages::INDEX,
+        });
+
+        let uniform_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Water Uniform Buffer"),
+            contents: bytemuck::bytes_of(&WaterUniforms::new(&params, horizon, 0.0)),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+        let ripples = Ripples::new();
+        let ripple_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Ripple Uniform Buffer"),
+            contents: bytemuck::bytes_of(&RippleUniforms::new(&params.ripples, &ripples, 1.0, 0.0)),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+        let uniform_bind_group_layout =
+            device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+                label: Some("Water Uniform Bind Group Layout"),
+                entries: &defs::WATER_UNIFORM_LAYOUT_ENTRIES,
+            });
+        let uniform_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("Water Uniform Bind Group"),
+            layout: &uniform_bind_group_layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: uniform_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: ripple_buffer.as_entire_binding(),
+                },
+            ],
+        });
+
+        let texture_bind_group_layout = defs::texture_bind_group_layout(device);
+        let sampler = defs::scene_sampler(device);
+        let input_bind_group =
+            Self::bind_input(device, &texture_bind_group_layout, &sampler, input);
+
+        // The scene is copied as it is stored, so targets of the same format as the input look
+        // right whether or not they encode sRGB
+        let pipelines = Pipelines::new(
+            device,
+            "water",
+            &[&texture_bind_group_layout, &uniform_bind_group_layout],
+            FragmentEntry {
+                srgb: "fs_main_water",
+                linear: "fs_main_water",
+            },
+            format,
+        )?;
+
+        let mut water = WaterRenderer {
+            indices: index_buffer,
+            vertex: vertex_buffer,
+            pipelines,
+            texture_bind_group_layout,
+            sampler,
+            input_bind_group,
+            params,
+            horizon,
+            uniform_buffer,
+            ripples,
+            ripple_buffer,
+            aspect: 1.0,
+            uniform_bind_group_layout,
+            uniform_bind_group,
+            simulation: None,
+            simulation_limit: u32::MAX,
+        };
+        water.set_params(device, params)?;
+        Ok(water)
+    }
+
+    // Builds the simulation, and water pipelines like the plain ones with its heightfield added
+    fn simulated(&self, device: &wgpu::Device) -> Result<Simulated, WaterError> {
+        let mut simulation = Simulation::new(device)?;
+        simulation.set_max_resolution(self.simulation_limit);
+        let pipelines = Pipelines::new(
+            device,
+            "simulated water",
+            &[
+                &self.texture_bind_group_layout,
+                &self.uniform_bind_group_layout,
+                simulation.render_layout(),
+            ],
+            FragmentEntry {
+                srgb: "fs_main_water_simulated",
+                linear: "fs_main_water_simulated",
+            },
+            self.pipelines.format(),
+        )?;
+        Ok(Simulated {
+            simulation,
+            pipelines,
+        })
+    }
+
+    fn bind_input(
+        device: &wgpu::Device,
+        layout: &wgpu::BindGroupLayout,
+        sampler: &wgpu::Sampler,
+        input: &wgpu::TextureView,
+    ) -> wgpu::BindGroup {
+        device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("Diffuse Bind Group"),
+            layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::TextureView(input),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::Sampler(sampler),
+                },
+            ],
+        })
+    }
+
+    // Covers the screen below the horizon, sampling the scene above it upside down
+    fn vertices(horizon: f32) -> [Vertex; 4] {
+        let top = 1.0 - 2.0 * horizon;
+        // The bottom of the screen is as far below the horizon as this is above it
+        let mirrored = 2.0 * horizon - 1.0;
+        [
+            Vertex {
+                position: [-1.0, top, 0.0],
+                tex_coords: [0.0, horizon],
+            },
+            Vertex {
+                position: [1.0, top, 0.0],
+                tex_coords: [1.0, horizon],
+            },
+            Vertex {
+                position: [-1.0, -1.0, 0.0],
+                tex_coords: [0.0, mirrored],
+            },
+            Vertex {
+                position: [1.0, -1.0, 0.0],
+                tex_coords: [1.0, mirrored],
+            },
+        ]
+    }
+
+    /// Reflects the scene in `input` from now on, e.g. after the scene texture was resized
+    pub fn set_input(&mut self, device: &wgpu::Device, input: &wgpu::TextureView) {
+        self.input_bind_group = Self::bind_input(
+            device,
+            &self.texture_bind_group_layout,
+            &self.sampler,
+            input,
+        );
+    }
+
+    /// Draws into targets of `format` from now on
+    ///
+    /// The input should be stored in the same format, or at least one with the same sRGB
+    /// encoding, as it is copied across without converting.
+    pub fn set_format(
+        &mut self,
+        device: &wgpu::Device,
+        format: wgpu::TextureFormat,
+    ) -> Result<(), WaterError> {
+        if let Some(simulated) = &mut self.simulation {
+            simulated.pipelines.set_format(device, format)?;
+        }
+        self.pipelines.set_format(device, format)
+    }
+
+    /// Changes the look of the water, takes effect from the next draw
+    ///
+    /// The horizon in `params` is ignored, it depends on the scene and is set with
+    /// [`WaterRenderer::set_horizon`] instead. The first parameters with a simulation build its
+    /// pipelines, which is the only way this can fail. The current parameters are kept if it does.
+    pub fn set_params(
+        &mut self,
+        device: &wgpu::Device,
+        params: WaterParams,
+    ) -> Result<(), WaterError> {
+        if params.simulation.is_some() && self.simulation.is_none() {
+            self.simulation = Some(self.simulated(device)?);
+        }
+        self.params = params;
+        Ok(())
+    }
+
+    /// Moves the top of the water to `horizon`, a fraction of the screen height from the top
+    pub fn set_horizon(&mut self, queue: &wgpu::Queue, horizon: f32) {
+        self.horizon = horizon;
+        queue.write_buffer(
+            &self.vertex,
+            0,
+            bytemuck::cast_slice(&Self::vertices(horizon)),
+        );
+    }
+
+    /// Tells the renderer the size of the targets it draws into, so the ripples stay round
+    pub fn set_size(&mut self, width: u32, height: u32) {
+        self.aspect = width.max(1) as f32 / height.max(1) as f32;
+    }
+
+    /// Starts a ripple at `position`, a fraction of the target's width and height from the top
+    /// left, `seconds` into the animation
+    ///
+    /// With a simulation in the parameters the drop disturbs the simulated water from the next
+    /// frame instead. Returns false, without adding one, if `position` is above the water.
+    ///
+    /// # Example
+    /// ```ignore
+    /// let position = [cursor.x as f32 / width as f32, cursor.y as f32 / height as f32];
+    /// water.add_ripple(position, clock.seconds());
+    /// ```
+    pub fn add_ripple(&mut self, position: [f32; 2], seconds: f32) -> bool {
+        if position[1] < self.horizon {
+            return false;
+        }
+        if let (Some(params), Some(simulated)) = (&self.params.simulation, &mut self.simulation) {
+            let down = (position[1] - self.horizon) / (1.0 - self.horizon);
+            simulated.simulation.add_drop(params, [position[0], down]);
+            return true;
+        }
+        // The water's texture coordinates run back up from the horizon
+        let centre = [position[0], 2.0 * self.horizon - position[1]];
+        self.ripples
+            .add(&self.params.ripples, self.aspect, centre, seconds);
+        true
+    }
+
+    /// Removes all the ripples straight away, and calms the simulated water
+    pub fn clear_ripples(&mut self) {
+        self.ripples.clear();
+        if let Some(simulated) = &mut self.simulation {
+            simulated.simulation.reset();
+        }
+    }
+
+    /// Caps the number of cells down the simulated water, e.g. at
+    /// [`FALLBACK_RESOLUTION`](crate::FALLBACK_RESOLUTION) on software adapters
+    pub fn limit_simulation(&mut self, resolution: u32) {
+        self.simulation_limit = resolution;
+        if let Some(simulated) = &mut self.simulation {
+            simulated.simulation.set_max_resolution(resolution);
+        }
+    }
+
+    /// The ripples currently on the water, e.g. to find how far they move it at a point
+    pub fn ripples(&self) -> &Ripples {
+        &self.ripples
+    }
+
+    /// Width of the targets over their height, as set by [`WaterRenderer::set_size`]
+    pub fn aspect(&self) -> f32 {
+        self.aspect
+    }
+
+    /// Records a pass drawing the water effect as it looks `seconds` into the animation
+    ///
+    /// The water is drawn over whatever `view` already holds. The uniforms are written straight
+    /// away, so they are in place when the encoder is submitted. With a simulation in the
+    /// parameters, it is stepped up to `seconds` first.
+    ///
+    /// # Example
+    /// ```ignore
+    /// water.encode(&device, &queue, &mut encoder, &view, clock.seconds());
+    /// queue.submit(std::iter::once(encoder.finish()));
+    /// ```
+    pub fn encode(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        encoder: &mut wgpu::CommandEncoder,
+        view: &wgpu::TextureView,
+        seconds: f32,
+    ) {
+        let uniforms = WaterUniforms::new(&self.params, self.horizon, seconds);
+        queue.write_buffer(&self.uniform_buffer, 0, bytemuck::bytes_of(&uniforms));
+        let ripples =
+            RippleUniforms::new(&self.params.ripples, &self.ripples, self.aspect, seconds);
+        queue.write_buffer(&self.ripple_buffer, 0, bytemuck::bytes_of(&ripples));
+        if let Some(simulated) = &mut self.simulation {
+            simulated.simulation.encode(
+                device,
+                queue,
+                encoder,
+                self.params.simulation.as_ref(),
+                self.aspect,
+                self.horizon,
+                seconds,
+            );
+        }
+        // Kept after the simulation is turned off, but only drawn while it is on
+        let simulated = self
+            .simulation
+            .as_ref()
+            .filter(|_| self.params.simulation.is_some());
+
+        let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("Wgpu render pass"),
+            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                view,
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Load,
+                    store: true,
+                },
+            })],
+            depth_stencil_attachment: None,
+        });
+
+        match simulated {
+            Some(simulated) => {
+                render_pass.set_pipeline(simulated.pipelines.current());
+                render_pass.set_bind_group(2, simulated.simulation.bind_group(), &[]);
+            }
+            None => render_pass.set_pipeline(self.pipelines.current()),
+        }
+        render_pass.set_bind_group(0, &self.input_bind_group, &[]);
+        render_pass.set_bind_group(1, &self.uniform_bind_group, &[]);
+        render_pass.set_vertex_buffer(0, self.vertex.slice(..));
+        render_pass.set_index_buffer(self.indices.slice(..), wgpu::IndexFormat::Uint16);
+        render_pass.draw_indexed(0..6, 0, 0..1);
+    }
+
+    /// Draws the water effect into `view` and submits it straight away
+    ///
+    /// # Example
+    /// ```ignore
+    /// water.render(&device, &queue, &view, clock.seconds());
+    /// ```
+    pub fn render(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        view: &wgpu::TextureView,
+        seconds: f32,
+    ) {
+        let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+            label: Some("Water encoder"),
+        });
+        self.encode(device, queue, &mut encoder, view, seconds);
+        queue.submit(std::iter::once(encoder.finish()));
+    }
+}
@@ -0,0 +1,159 @@
+use crate::params::RippleParams;
+
+/// Largest number of ripples on the water at once. Adding another replaces the oldest
+pub const MAX_RIPPLES: usize = 64;
+
+// Weaker than this fraction of their starting strength, ripples are dropped
+const FADED: f32 = 0.01;
+
+#[derive(Copy, Clone, Debug)]
+struct Ripple {
+    centre: [f32; 2],
+    start: f32,
+}
+
+/// Expanding rings on the water, started by clicks or by the caller
+///
+/// Positions are in the water's texture coordinates, as used by [`crate::noise`], so they can be
+/// compared with the noise offset.
+#[derive(Clone, Debug, Default)]
+pub struct Ripples {
+    ripples: Vec<Ripple>,
+}
+
+impl Ripples {
+    pub fn new() -> Self {
+        Ripples::default()
+    }
+
+    /// Starts a ripple at `centre` that begins to spread `seconds` into the animation
+    ///
+    /// Ripples that have died away are dropped first. If there are still
+    /// [`MAX_RIPPLES`] the oldest makes way.
+    pub fn add(&mut self, params: &RippleParams, aspect: f32, centre: [f32; 2], seconds: f32) {
+        self.ripples
+            .retain(|ripple| alive(params, aspect, seconds - ripple.start));
+        if self.ripples.len() == MAX_RIPPLES {
+            self.ripples.remove(0);
+        }
+        self.ripples.push(Ripple {
+            centre,
+            start: seconds,
+        });
+    }
+
+    pub fn clear(&mut self) {
+        self.ripples.clear();
+    }
+
+    /// Centres and ages of the ripples visible `seconds` into the animation, oldest first
+    pub fn active<'a>(
+        &'a self,
+        params: &'a RippleParams,
+        aspect: f32,
+        seconds: f32,
+    ) -> impl Iterator<Item = ([f32; 2], f32)> + 'a {
+        self.ripples.iter().filter_map(move |ripple| {
+            let age = seconds - ripple.start;
+            alive(params, aspect, age).then_some((ripple.centre, age))
+        })
+    }
+
+    /// Distance, in texture coordinates, the ripples move the water at `tex_coords`
+    ///
+    /// `aspect` is the width of the target over its height. Matches `ripple_offset` in the shader.
+    ///
+    /// # Example
+    /// ```ignore
+    /// let [x, y] = ripples.offset(&params.ripples, 16.0 / 9.0, [0.5, 0.3], clock.seconds());
+    /// ```
+    pub fn offset(
+        &self,
+        params: &RippleParams,
+        aspect: f32,
+        tex_coords: [f32; 2],
+        seconds: f32,
+    ) -> [f32; 2] {
+        let mut offset = [0.0, 0.0];
+        for (centre, age) in self.active(params, aspect, seconds) {
+            let ring = ring(params, aspect, centre, age, tex_coords);
+            offset = [offset[0] + ring[0], offset[1] + ring[1]];
+        }
+        offset
+    }
+}
+
+// One ripple's push on the water, measured in screen heights so the rings stay round
+fn ring(
+    params: &RippleParams,
+    aspect: f32,
+    centre: [f32; 2],
+    age: f32,
+    tex_coords: [f32; 2],
+) -> [f32; 2] {
+    let delta = [
+        (tex_coords[0] - centre[0]) * aspect,
+        tex_coords[1] - centre[1],
+    ];
+    let distance = (delta[0] * delta[0] + delta[1] * delta[1])
+        .sqrt()
+        .max(0.0001);
+    // Wavelengths between this point and the front of the ring
+    let behind = (params.speed * age - distance) / params.wavelength;
+    let height = (behind * std::f32::consts::TAU).sin()
+        * (-behind * behind).exp()
+        * (-params.damping * age).exp();
+    let push = height * params.strength / distance;
+    [delta[0] * push / aspect, delta[1] * push]
+}
+
+// Whether a ripple this old still shows
+fn alive(params: &RippleParams, aspect: f32, age: f32) -> bool {
+    // Far enough past the diagonal of the screen to be off it whichever way it spread
+    let reach = (aspect * aspect + 1.0).sqrt() + 3.0 * params.wavelength;
+    age >= 0.0 && (-params.damping * age).exp() >= FADED && params.speed * age <= reach
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ripples_die_away() {
+        let params = RippleParams::default();
+        let mut ripples = Ripples::new();
+        ripples.add(&params, 1.0, [0.5, 0.2], 1.0);
+        assert_eq!(ripples.active(&params, 1.0, 1.5).count(), 1);
+        assert_eq!(ripples.active(&params, 1.0, 0.5).count(), 0);
+        assert_eq!(ripples.active(&params, 1.0, 100.0).count(), 0);
+    }
+
+    #[test]
+    fn oldest_ripple_makes_way() {
+        let params = RippleParams::default();
+        let mut ripples = Ripples::new();
+        for n in 0..=MAX_RIPPLES {
+            ripples.add(&params, 1.0, [n as f32 / 100.0, 0.2], 1.0);
+        }
+        let active: Vec<_> = ripples.active(&params, 1.0, 1.1).collect();
+        assert_eq!(active.len(), MAX_RIPPLES);
+        assert_eq!(active[0].0, [0.01, 0.2]);
+    }
+
+    #[test]
+    fn rings_push_outwards_evenly() {
+        let params = RippleParams::default();
+        let mut ripples = Ripples::new();
+        ripples.add(&params, 2.0, [0.5, 0.3], 0.0);
+
+        // Points the same distance away on screen, with twice the width in x, a quarter of a
+        // wave inside the front where the push is strongest
+        let radius = params.speed * 0.5 - params.wavelength / 4.0;
+        let right = ripples.offset(&params, 2.0, [0.5 + radius / 2.0, 0.3], 0.5);
+        let below = ripples.offset(&params, 2.0, [0.5, 0.3 - radius], 0.5);
+        assert!(right[0] > params.strength * 0.1);
+        assert!((right[0] * 2.0 + below[1]).abs() < 1e-6);
+        assert_eq!(right[1], 0.0);
+        assert!(below[0].abs() < 1e-9);
+    }
+}
@@ -2,13 +2,11 @@
 struct VertexOutput {
     @builtin(position) clip_position: vec4<f32>,
     @location(0) tex_coords: vec2<f32>,
-    @location(1) time: f32
 };
 
 struct VertexInput {
     @location(0) pos: vec3<f32>,
     @location(1) tex_coords: vec2<f32>,
-    @location(2) seed: f32,
 }
 
 @vertex
@@ -16,7 +14,6 @@ fn vs_main(model: VertexInput) -> VertexOutput {
     var out: VertexOutput;
     out.tex_coords = model.tex_coords;
     out.clip_position = vec4<f32>(model.pos, 1.0);
-    out.time = model.seed;
     return out;
 }
 
@@ -25,18 +22,117 @@ var t_diffuse: texture_2d<f32>;
 @group(0)@binding(1)
 var s_diffuse: sampler;
 
+// Mirrors defs::WaterUniforms
+struct WaterUniforms {
+    time: f32,
+    amplitude: f32,
+    bias: f32,
+    noise_scale: f32,
+    horizon: f32,
+    falloff_rate: f32,
+    falloff_min: f32,
+    falloff_max: f32,
+    noise_kind: u32,
+    hash: u32,
+    octaves: u32,
+    lacunarity: f32,
+    gain: f32,
+    warp: f32,
+    wind: u32,
+    wind_x: f32,
+    wind_y: f32,
+    anisotropy: f32,
+    drift: f32,
+    gust: f32,
+    simulation: f32,
+};
+
+@group(1) @binding(0)
+var<uniform> water: WaterUniforms;
+
+// Mirrors defs::RippleUniforms, the array length is ripples::MAX_RIPPLES
+struct Ripples {
+    count: u32,
+    aspect: f32,
+    speed: f32,
+    wavelength: f32,
+    damping: f32,
+    strength: f32,
+    // Centre in texture coordinates, then the age in seconds
+    ripples: array<vec4<f32>, 64>,
+};
+
+@group(1) @binding(1)
+var<uniform> ripples: Ripples;
+
+// Mirrors defs::StepUniforms, only the size is used here
+struct Step {
+    size: vec2<u32>,
+    courant: vec2<f32>,
+    cell: vec2<f32>,
+    keep: f32,
+    drop_count: u32,
+    drops: array<vec4<f32>, 16>,
+};
+
+// Simulated heightfield covering the water, row by row from the horizon down, see simulation.wgsl
+@group(2) @binding(0)
+var<storage, read> heights: array<vec2<f32>>;
+@group(2) @binding(1)
+var<uniform> sim: Step;
+
 @fragment
 fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
     return textureSample(t_diffuse, s_diffuse, in.tex_coords);
 }
 
-fn rand(tex_coords: vec2<f32>) -> vec2<f32> {
+fn linear_to_srgb(color: vec3<f32>) -> vec3<f32> {
+    let low = color * 12.92;
+    let high = 1.055 * pow(color, vec3(1.0 / 2.4)) - 0.055;
+    return select(high, low, color <= vec3(0.0031308));
+}
+
+// Same as fs_main for targets that don't encode sRGB themselves
+@fragment
+fn fs_main_encode_srgb(in: VertexOutput) -> @location(0) vec4<f32> {
+    let color = textureSample(t_diffuse, s_diffuse, in.tex_coords);
+    return vec4<f32>(linear_to_srgb(color.rgb), color.a);
+}
+
+fn rand_sine(tex_coords: vec2<f32>) -> vec2<f32> {
     // Psudo random number generator
     let tex_coords = vec2( dot(tex_coords,vec2(127.1,311.7)),
               dot(tex_coords,vec2(269.5,183.3)) );
     return -1.0 + 2.0 * fract(sin(tex_coords) * 43758.5453123);
 }
 
+fn pcg2d(v: vec2<u32>) -> vec2<u32> {
+    // Integer hash from "Hash Functions for GPU Rendering", Jarzynski and Olano
+    var h = v * 1664525u + 1013904223u;
+    h.x = h.x + h.y * 1664525u;
+    h.y = h.y + h.x * 1664525u;
+    h = h ^ (h >> vec2(16u));
+    h.x = h.x + h.y * 1664525u;
+    h.y = h.y + h.x * 1664525u;
+    h = h ^ (h >> vec2(16u));
+    return h;
+}
+
+fn rand_pcg(tex_coords: vec2<f32>) -> vec2<f32> {
+    // Only called with whole numbers. The cells repeat every NoiseParams::PCG_PERIOD, so the
+    // time can wrap, and the top 24 bits convert to float exactly on every adapter
+    let cell = bitcast<vec2<u32>>(vec2<i32>(tex_coords)) & vec2(255u);
+    return -1.0 + 2.0 * (vec2<f32>(pcg2d(cell) >> vec2(8u)) / 16777216.0);
+}
+
+fn rand(tex_coords: vec2<f32>) -> vec2<f32> {
+    // Hash picked by water.hash, numbered as in params::NoiseHash
+    if water.hash == 1u {
+        return rand_pcg(tex_coords);
+    }
+    return rand_sine(tex_coords);
+}
+
 fn noise(tex_coords: vec2<f32>) -> f32 {
     // Gradient noise
     let i = floor(tex_coords);
@@ -50,13 +146,168 @@ fn noise(tex_coords: vec2<f32>) -> f32 {
                      dot( rand(i + vec2(1.0,1.0) ), f - vec2(1.0,1.0) ), u.x), u.y);
 }
 
+fn simplex(tex_coords: vec2<f32>) -> f32 {
+    // Simplex noise over the same gradients as noise
+    // Skews the triangle grid onto squares and back
+    let k1 = 0.366025404; // (sqrt(3) - 1) / 2
+    let k2 = 0.211324865; // (3 - sqrt(3)) / 6
+
+    let i = floor(tex_coords + (tex_coords.x + tex_coords.y) * k1);
+    let a = tex_coords - i + (i.x + i.y) * k2;
+    let o = select(vec2(0.0, 1.0), vec2(1.0, 0.0), a.x > a.y);
+    let b = a - o + k2;
+    let c = a - 1.0 + 2.0 * k2;
+
+    let h = max(0.5 - vec3(dot(a, a), dot(b, b), dot(c, c)), vec3(0.0));
+    let n = h * h * h * h * vec3(dot(a, rand(i)), dot(b, rand(i + o)), dot(c, rand(i + 1.0)));
+    return dot(n, vec3(70.0));
+}
+
+fn worley(tex_coords: vec2<f32>) -> f32 {
+    // Distance to the nearest of one random point per cell, moved to centre on zero
+    let i = floor(tex_coords);
+    let f = fract(tex_coords);
+
+    var nearest = 8.0;
+    for (var y = -1; y <= 1; y = y + 1) {
+        for (var x = -1; x <= 1; x = x + 1) {
+            let cell = vec2(f32(x), f32(y));
+            let feature = cell + 0.5 + 0.5 * rand(i + cell);
+            nearest = min(nearest, length(feature - f));
+        }
+    }
+    return nearest - 0.5;
+}
+
+fn base_noise(tex_coords: vec2<f32>) -> f32 {
+    // Noise picked by water.noise_kind, numbered as in params::NoiseKind
+    if water.noise_kind == 1u {
+        return simplex(tex_coords);
+    }
+    if water.noise_kind == 2u {
+        return worley(tex_coords);
+    }
+    return noise(tex_coords);
+}
+
+fn fbm(tex_coords: vec2<f32>) -> f32 {
+    // Layers of noise at rising frequency, divided by the total strength so the range doesn't
+    // depend on the number of octaves
+    var sum = 0.0;
+    var total = 0.0;
+    var strength = 1.0;
+    var frequency = 1.0;
+    for (var octave = 0u; octave < water.octaves; octave = octave + 1u) {
+        sum = sum + strength * base_noise(tex_coords * frequency);
+        total = total + strength;
+        strength = strength * water.gain;
+        frequency = frequency * water.lacunarity;
+    }
+    return sum / total;
+}
+
+fn water_noise(tex_coords: vec2<f32>) -> f32 {
+    // Domain warping: looks the noise up at a point moved by two more samples of it, taken far
+    // enough apart to be unrelated
+    if water.warp > 0.0 {
+        let offset = vec2(fbm(tex_coords), fbm(tex_coords + vec2(5.2, 1.3)));
+        return fbm(tex_coords + water.warp * offset);
+    }
+    return fbm(tex_coords);
+}
+
+fn water_offset(tex_coords: vec2<f32>) -> vec2<f32> {
+    // Distance the water at tex_coords samples away from its mirror image
+    let falloff = clamp((tex_coords.y - water.horizon) * -water.falloff_rate, water.falloff_min, water.falloff_max);
+    if water.wind == 0u {
+        // Without wind the noise drifts diagonally and moves both axes the same
+        let coords = tex_coords * water.noise_scale + water.time;
+        let random = (water_noise(coords) + water.bias) * water.amplitude * falloff;
+        return vec2(random);
+    }
+
+    // Looks the noise up in the wind's frame, with x along the wind and y across it, so the
+    // ripples drift downwind and are stretched across it
+    let along = vec2(water.wind_x, water.wind_y);
+    let across = vec2(-along.y, along.x);
+    let scaled = tex_coords * water.noise_scale;
+    let coords = vec2(dot(scaled, along) - water.drift, dot(scaled, across) / water.anisotropy);
+
+    // Separate noise for each direction, mostly pushing the water along the wind
+    let push = water_noise(coords);
+    let sway = water_noise(coords + vec2(31.0, 17.0)) / water.anisotropy;
+    let offset = along * push + across * sway + water.bias;
+    return offset * water.amplitude * water.gust * falloff;
+}
+
+fn ripple_offset(tex_coords: vec2<f32>) -> vec2<f32> {
+    // Push of the rings spreading from clicks, measured in screen heights so they stay round
+    var offset = vec2(0.0);
+    for (var i = 0u; i < ripples.count; i = i + 1u) {
+        let ripple = ripples.ripples[i];
+        let delta = (tex_coords - ripple.xy) * vec2(ripples.aspect, 1.0);
+        let distance = max(length(delta), 0.0001);
+        // Wavelengths between this point and the front of the ring
+        let behind = (ripples.speed * ripple.z - distance) / ripples.wavelength;
+        let height = sin(behind * 6.2831855) * exp(-behind * behind) * exp(-ripples.damping * ripple.z);
+        let push = height * ripples.strength / distance;
+        offset = offset + vec2(delta.x * push / ripples.aspect, delta.y * push);
+    }
+    return offset;
+}
+
+fn height(cell: vec2<i32>) -> f32 {
+    let clamped = clamp(cell, vec2(0), vec2<i32>(sim.size) - 1);
+    return heights[u32(clamped.y) * sim.size.x + u32(clamped.x)].x;
+}
+
+// Height of the simulated water at `uv`, a fraction across and down it, filtered linearly
+fn sample_height(uv: vec2<f32>) -> f32 {
+    let position = uv * vec2<f32>(sim.size) - 0.5;
+    let corner = floor(position);
+    let t = position - corner;
+    let cell = vec2<i32>(corner);
+    let top = mix(height(cell), height(cell + vec2(1, 0)), t.x);
+    let bottom = mix(height(cell + vec2(0, 1)), height(cell + vec2(1, 1)), t.x);
+    return mix(top, bottom, t.y);
+}
+
+fn simulation_offset(tex_coords: vec2<f32>) -> vec2<f32> {
+    // The reflection bends with the slope of the simulated water
+    let depth = 1.0 - water.horizon;
+    if water.simulation == 0.0 || depth <= 0.0 {
+        return vec2(0.0);
+    }
+    let uv = vec2(tex_coords.x, (water.horizon - tex_coords.y) / depth);
+    let texel = 1.0 / vec2<f32>(sim.size);
+    let left = sample_height(uv - vec2(texel.x, 0.0));
+    let right = sample_height(uv + vec2(texel.x, 0.0));
+    let up = sample_height(uv - vec2(0.0, texel.y));
+    let down = sample_height(uv + vec2(0.0, texel.y));
+    // Slope per screen height, the texture coordinates run up the screen
+    let slope = vec2((right - left) / (2.0 * sim.cell.x), (down - up) / (2.0 * sim.cell.y));
+    return vec2(slope.x, -slope.y) * water.simulation;
+}
+
+// Samples the reflection moved by `offset`, kept within the scene above the horizon
+fn reflection(tex_coords: vec2<f32>, offset: vec2<f32>) -> vec4<f32> {
+    return textureSample(t_diffuse, s_diffuse, vec2<f32>(clamp(tex_coords.x + offset.x, 0.0, 1.0), clamp(tex_coords.y + offset.y, 0.0, water.horizon)));
+}
+
 @fragment
 fn fs_main_water(in: VertexOutput) -> @location(0) vec4<f32> {
     // Used for testing noise function
-    // let random = noise(vec2<f32>(in.time + in.tex_coords.x, in.time + in.tex_coords.y));
+    // let random = water_noise(in.tex_coords * water.noise_scale + water.time);
     // return vec4<f32>(random, random, random, 1.0);
 
     // Creates random offset for each pixel
-    let random = (noise(vec2<f32>(in.time + in.tex_coords.x, in.time + in.tex_coords.y)) - 0.5) * 0.01 * clamp((in.tex_coords.y - 0.5) * -3.0, 0.3, 1.0);
-    return textureSample(t_diffuse, s_diffuse, vec2<f32>(clamp(in.tex_coords.x + random, 0.0, 1.0), clamp(in.tex_coords.y + random, 0.0, 0.5)));
+    let offset = water_offset(in.tex_coords) + ripple_offset(in.tex_coords);
+    return reflection(in.tex_coords, offset);
+}
+
+// Only used with a simulation, so the heightfield in group 2 isn't needed otherwise
+@fragment
+fn fs_main_water_simulated(in: VertexOutput) -> @location(0) vec4<f32> {
+    let offset = water_offset(in.tex_coords) + ripple_offset(in.tex_coords) + simulation_offset(in.tex_coords);
+    return reflection(in.tex_coords, offset);
 }
@@ -0,0 +1,413 @@
+use crate::defs::{self, Pipelines, StepUniforms};
+use crate::error::WaterError;
+use crate::params::SimulationParams;
+use wgpu::include_wgsl;
+use wgpu::util::DeviceExt;
+
+/// Largest number of drops added to the water in one frame, the rest wait for the next
+pub(crate) const MAX_DROPS: usize = 16;
+
+/// Cells down the water on software adapters, which can't step a finer heightfield every frame
+pub const FALLBACK_RESOLUTION: u32 = 64;
+
+// Largest sum of the squared Courant numbers. The leapfrog step is stable up to 1, this leaves a
+// margin so the waves keep their shape
+const COURANT_LIMIT: f32 = 0.5;
+// Steps per second however slow the waves are, so drops and damping stay smooth
+const MIN_STEP_RATE: f32 = 60.0;
+// Longest time stepped in one frame. After a longer pause the water falls behind the animation
+// rather than stalling it
+const MAX_FRAME_SECONDS: f64 = 0.25;
+// Most steps dispatched in one frame, so fast waves on a fine grid can't stall the gpu. Beyond it
+// the water falls behind the animation too
+const MAX_STEPS_PER_FRAME: u32 = 32;
+// Matches @workgroup_size in simulation.wgsl
+const WORKGROUP_SIZE: u32 = 8;
+// Each cell holds its height now and a step ago
+const CELL_BYTES: u64 = std::mem::size_of::<[f32; 2]>() as u64;
+
+// The two heightfields stepped back and forth between
+struct Grid {
+    size: [u32; 2],
+    // Never read directly, but owning them keeps the heightfields alive
+    _buffers: [wgpu::Buffer; 2],
+    // Entry i reads heightfield i and writes the other one
+    compute_bind_groups: [wgpu::BindGroup; 2],
+    // Entry i lets the water pass sample heightfield i
+    render_bind_groups: [wgpu::BindGroup; 2],
+}
+
+/// Heightfield of the water stepped by the wave equation in compute passes
+///
+/// Disturbances spread from where they land, reflect off the edges of the screen and die away.
+/// The heightfield covers the water from the horizon to the bottom of the screen.
+pub(crate) struct Simulation {
+    drops_pipeline: wgpu::ComputePipeline,
+    step_pipeline: wgpu::ComputePipeline,
+    compute_layout: wgpu::BindGroupLayout,
+    render_layout: wgpu::BindGroupLayout,
+    uniform_buffer: wgpu::Buffer,
+    grid: Grid,
+    // Which heightfield is the latest
+    current: usize,
+    // Animation time the water has been stepped to, None until the first frame
+    time: Option<f64>,
+    // Centre as fractions across and down the water, then the radius and depth in screen heights
+    drops: Vec<[f32; 4]>,
+    // Fraction of a raindrop owed, and the number landed so far to scatter the next
+    rain: f64,
+    raindrops: u32,
+    max_resolution: u32,
+    // Most cells a heightfield can hold and across or down it, from the device's limits
+    max_cells: u64,
+    max_side: u32,
+}
+
+impl Simulation {
+    pub fn new(device: &wgpu::Device) -> Result<Self, WaterError> {
+        let compute_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("Simulation Bind Group Layout"),
+            entries: &defs::SIMULATION_LAYOUT_ENTRIES,
+        });
+        let render_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("Heightfield Bind Group Layout"),
+            entries: &defs::HEIGHTFIELD_LAYOUT_ENTRIES,
+        });
+
+        device.push_error_scope(wgpu::ErrorFilter::Validation);
+        let shader = device.create_shader_module(include_wgsl!("simulation.wgsl"));
+        let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Simulation Pipeline Layout"),
+            bind_group_layouts: &[&compute_layout],
+            push_constant_ranges: &[],
+        });
+        let pipeline = |entry_point| {
+            device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+                label: Some(entry_point),
+                layout: Some(&layout),
+                module: &shader,
+                entry_point,
+            })
+        };
+        let drops_pipeline = pipeline("add_drops");
+        let step_pipeline = pipeline("step_waves");
+        Pipelines::check(device, "simulation")?;
+
+        let uniform_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Simulation Uniform Buffer"),
+            contents: bytemuck::bytes_of(&StepUniforms::new([1, 1], [0.0; 2], [1.0; 2], 1.0, &[])),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+        // Flat and never stepped until the simulation is turned on
+        let grid = Grid::new(
+            device,
+            &compute_layout,
+            &render_layout,
+            &uniform_buffer,
+            [1, 1],
+        );
+
+        Ok(Simulation {
+            drops_pipeline,
+            step_pipeline,
+            compute_layout,
+            render_layout,
+            uniform_buffer,
+            grid,
+            current: 0,
+            time: None,
+            drops: Vec::new(),
+            rain: 0.0,
+            raindrops: 0,
+            max_resolution: u32::MAX,
+            max_cells: device.limits().max_storage_buffer_binding_size as u64 / CELL_BYTES,
+            max_side: device
+                .limits()
+                .max_compute_workgroups_per_dimension
+                .saturating_mul(WORKGROUP_SIZE),
+        })
+    }
+
+    /// Layout of [`Simulation::bind_group`], for the water pipeline
+    pub fn render_layout(&self) -> &wgpu::BindGroupLayout {
+        &self.render_layout
+    }
+
+    /// Lets the water pass sample the latest heightfield
+    pub fn bind_group(&self) -> &wgpu::BindGroup {
+        &self.grid.render_bind_groups[self.current]
+    }
+
+    /// Caps the number of cells down the water, whatever the parameters ask for
+    pub fn set_max_resolution(&mut self, resolution: u32) {
+        self.max_resolution = resolution.max(1);
+    }
+
+    /// Drops a stone at `position`, fractions across and down the water from the top left
+    pub fn add_drop(&mut self, params: &SimulationParams, position: [f32; 2]) {
+        self.drops
+            .push([position[0], position[1], params.drop_size, params.drop_size]);
+    }
+
+    /// Calms the water, it starts again from flat on the next frame
+    pub fn reset(&mut self) {
+        self.time = None;
+        self.drops.clear();
+    }
+
+    /// Records the passes stepping the water up to `seconds` into the animation
+    ///
+    /// Going back in time, or changing the size of the heightfield, starts again from flat water.
+    #[allow(clippy::too_many_arguments)]
+    pub fn encode(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        encoder: &mut wgpu::CommandEncoder,
+        params: Option<&SimulationParams>,
+        aspect: f32,
+        horizon: f32,
+        seconds: f32,
+    ) {
+        // Fraction of the screen covered by the water
+        let depth = 1.0 - horizon;
+        let Some(params) = params.filter(|_| depth > 0.0) else {
+            self.reset();
+            return;
+        };
+
+        let resolution = params.resolution.clamp(1, self.max_resolution);
+        let size = grid_size(resolution, aspect, self.max_cells, self.max_side);
+        let seconds = seconds as f64;
+        let time = match self.time {
+            Some(time) if time <= seconds && size == self.grid.size => time,
+            _ => {
+                self.grid = Grid::new(
+                    device,
+                    &self.compute_layout,
+                    &self.render_layout,
+                    &self.uniform_buffer,
+                    size,
+                );
+                self.current = 0;
+                seconds
+            }
+        };
+        let time = time.max(seconds - MAX_FRAME_SECONDS);
+
+        let cell = [aspect / size[0] as f32, depth / size[1] as f32];
+        let rate = step_rate(params.speed, cell);
+        let steps = frame_steps(seconds - time, rate);
+        // Only the time actually stepped, the rest is stepped with the next frame
+        let stepped = steps as f64 / rate as f64;
+        self.time = Some(time + stepped);
+
+        self.rain += params.rain.max(0.0) as f64 * stepped;
+        while self.rain >= 1.0 {
+            self.rain -= 1.0;
+            self.raindrops = self.raindrops.wrapping_add(1);
+            let position = [random(2 * self.raindrops), random(2 * self.raindrops + 1)];
+            self.add_drop(params, position);
+        }
+
+        let count = self.drops.len().min(MAX_DROPS);
+        let drops: Vec<[f32; 4]> = self
+            .drops
+            .drain(..count)
+            .map(|[x, y, radius, drop_depth]| {
+                [x * size[0] as f32, y * size[1] as f32, radius, drop_depth]
+            })
+            .collect();
+        let step = 1.0 / rate;
+        let courant = cell.map(|cell| (params.speed * step / cell).powi(2));
+        let keep = (-params.damping.max(0.0) * step).exp();
+        let uniforms = StepUniforms::new(size, courant, cell, keep, &drops);
+        queue.write_buffer(&self.uniform_buffer, 0, bytemuck::bytes_of(&uniforms));
+
+        let mut pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+            label: Some("Simulation pass"),
+        });
+        let workgroups = size.map(|cells| cells.div_ceil(WORKGROUP_SIZE));
+        let passes = (!drops.is_empty())
+            .then_some(&self.drops_pipeline)
+            .into_iter()
+            .chain(std::iter::repeat_n(&self.step_pipeline, steps as usize));
+        for pipeline in passes {
+            pass.set_pipeline(pipeline);
+            pass.set_bind_group(0, &self.grid.compute_bind_groups[self.current], &[]);
+            pass.dispatch_workgroups(workgroups[0], workgroups[1], 1);
+            self.current = 1 - self.current;
+        }
+    }
+}
+
+impl Grid {
+    fn new(
+        device: &wgpu::Device,
+        compute_layout: &wgpu::BindGroupLayout,
+        render_layout: &wgpu::BindGroupLayout,
+        uniform_buffer: &wgpu::Buffer,
+        size: [u32; 2],
+    ) -> Self {
+        // New buffers are zeroed, which is flat water
+        let cells = size[0] as u64 * size[1] as u64;
+        let buffers = [0, 1].map(|_| {
+            device.create_buffer(&wgpu::BufferDescriptor {
+                label: Some("Heightfield"),
+                size: cells * CELL_BYTES,
+                usage: wgpu::BufferUsages::STORAGE,
+                mapped_at_creation: false,
+            })
+        });
+
+        let compute_bind_groups = [0, 1].map(|i| {
+            device.create_bind_group(&wgpu::BindGroupDescriptor {
+                label: Some("Simulation Bind Group"),
+                layout: compute_layout,
+                entries: &[
+                    wgpu::BindGroupEntry {
+                        binding: 0,
+                        resource: uniform_buffer.as_entire_binding(),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 1,
+                        resource: buffers[i].as_entire_binding(),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 2,
+                        resource: buffers[1 - i].as_entire_binding(),
+                    },
+                ],
+            })
+        });
+        let render_bind_groups = buffers.each_ref().map(|buffer| {
+            device.create_bind_group(&wgpu::BindGroupDescriptor {
+                label: Some("Heightfield Bind Group"),
+                layout: render_layout,
+                entries: &[
+                    wgpu::BindGroupEntry {
+                        binding: 0,
+                        resource: buffer.as_entire_binding(),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 1,
+                        resource: uniform_buffer.as_entire_binding(),
+                    },
+                ],
+            })
+        });
+
+        Grid {
+            size,
+            _buffers: buffers,
+            compute_bind_groups,
+            render_bind_groups,
+        }
+    }
+}
+
+// Cells across and down for `resolution` cells down the water, fewer when the heightfield would
+// be too large to bind or dispatch. Shrinks both ways so the cells stay the same shape
+fn grid_size(resolution: u32, aspect: f32, max_cells: u64, max_side: u32) -> [u32; 2] {
+    let down = resolution
+        .min(max_side)
+        .min((max_side as f32 / aspect) as u32)
+        .min((max_cells as f64 / aspect as f64).sqrt() as u32)
+        .max(1);
+    // Rounding can still leave a few cells too many across
+    let across = ((down as f32 * aspect).round() as u64)
+        .min(max_side as u64)
+        .min(max_cells / down as u64)
+        .max(1);
+    [across as u32, down]
+}
+
+// Steps a second for waves moving at `speed` over cells `cell` screen heights across and down,
+// enough that no wave crosses more than a cell in one
+fn step_rate(speed: f32, cell: [f32; 2]) -> f32 {
+    let reach = speed * (cell[0].powi(-2) + cell[1].powi(-2)).sqrt();
+    (reach / COURANT_LIMIT.sqrt()).max(MIN_STEP_RATE)
+}
+
+// Whole steps at `rate` a second that fit in `seconds`, up to the most one frame may take
+fn frame_steps(seconds: f64, rate: f32) -> u32 {
+    (seconds * rate as f64)
+        .floor()
+        .min(MAX_STEPS_PER_FRAME as f64) as u32
+}
+
+// Integer hash of `n` to [0, 1), scatters the rain the same way every run
+fn random(n: u32) -> f32 {
+    let state = n.wrapping_mul(747796405).wrapping_add(2891336453);
+    let word = ((state >> ((state >> 28) + 4)) ^ state).wrapping_mul(277803737);
+    (((word >> 22) ^ word) >> 8) as f32 / 16777216.0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn steps_stay_stable() {
+        for (speed, cell) in [
+            (0.2, [1.0 / 128.0, 0.5 / 128.0]),
+            (3.0, [0.001, 0.02]),
+            (0.0, [0.1; 2]),
+        ] {
+            let step = 1.0 / step_rate(speed, cell);
+            let courant: f32 = cell.iter().map(|cell| (speed * step / cell).powi(2)).sum();
+            assert!(
+                courant <= COURANT_LIMIT * 1.0001,
+                "{} at {}",
+                courant,
+                speed
+            );
+            assert!(step <= 1.0 / MIN_STEP_RATE);
+        }
+    }
+
+    #[test]
+    fn fast_waves_fall_behind() {
+        let cell = [1.0 / 4096.0; 2];
+        for speed in [0.2, 1e3, 1e9] {
+            let rate = step_rate(speed, cell);
+            assert!(frame_steps(MAX_FRAME_SECONDS, rate) <= MAX_STEPS_PER_FRAME);
+        }
+        // Slower waves still take every step
+        let rate = step_rate(0.2, [1.0 / 128.0, 0.5 / 128.0]);
+        assert_eq!(frame_steps(0.1, rate), (0.1 * rate as f64).floor() as u32);
+        assert_eq!(frame_steps(0.0, rate), 0);
+    }
+
+    #[test]
+    fn oversized_grids_fit_the_limits() {
+        let limits = wgpu::Limits::downlevel_defaults();
+        let max_cells = limits.max_storage_buffer_binding_size as u64 / CELL_BYTES;
+        let max_side = limits.max_compute_workgroups_per_dimension * WORKGROUP_SIZE;
+        for (resolution, aspect) in [
+            (4096, 1920.0 / 1080.0),
+            (u32::MAX, 1.0),
+            (2, 1e6),
+            (3, 1e-6),
+        ] {
+            let size = grid_size(resolution, aspect, max_cells, max_side);
+            assert!(size[0] as u64 * size[1] as u64 <= max_cells, "{:?}", size);
+            assert!(size.iter().all(|&side| (1..=max_side).contains(&side)));
+        }
+
+        // Only as small as it has to be, and the same shape
+        let aspect: f32 = 1920.0 / 1080.0;
+        let [across, down] = grid_size(4096, aspect, max_cells, max_side);
+        assert!((down as f64 + 1.0).powi(2) * aspect as f64 > max_cells as f64);
+        assert!((across as f32 / down as f32 - aspect).abs() < 0.01);
+        assert_eq!(grid_size(128, 2.0, max_cells, max_side), [256, 128]);
+    }
+
+    #[test]
+    fn rain_lands_on_the_water() {
+        for n in 0..10000 {
+            assert!((0.0..1.0).contains(&random(n)));
+        }
+        assert_ne!(random(1), random(2));
+    }
+}
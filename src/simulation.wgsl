@@ -0,0 +1,63 @@
+// Steps the heightfield of the simulated water, see simulation::Simulation. Each pass reads the
+// heightfield from `heights` and writes the next one to `next`, then the two swap
+
+// Mirrors defs::StepUniforms, the array length is simulation::MAX_DROPS
+struct Step {
+    size: vec2<u32>,
+    // (speed * step / cell)^2 across and down
+    courant: vec2<f32>,
+    // Width and height of a cell in screen heights
+    cell: vec2<f32>,
+    keep: f32,
+    drop_count: u32,
+    // Centre in cells, then the radius and depth of the dent in screen heights
+    drops: array<vec4<f32>, 16>,
+};
+
+@group(0) @binding(0)
+var<uniform> sim: Step;
+// Height of each cell now and a step ago, row by row from the horizon down
+@group(0) @binding(1)
+var<storage, read> heights: array<vec2<f32>>;
+@group(0) @binding(2)
+var<storage, read_write> next: array<vec2<f32>>;
+
+fn index(cell: vec2<i32>) -> u32 {
+    return u32(cell.y) * sim.size.x + u32(cell.x);
+}
+
+// Cells past the edges copy the nearest one, so waves reflect off them
+fn height(cell: vec2<i32>) -> vec2<f32> {
+    return heights[index(clamp(cell, vec2(0), vec2<i32>(sim.size) - 1))];
+}
+
+@compute @workgroup_size(8, 8)
+fn add_drops(@builtin(global_invocation_id) id: vec3<u32>) {
+    if any(id.xy >= sim.size) {
+        return;
+    }
+    let cell = vec2<i32>(id.xy);
+    var dent = 0.0;
+    for (var i = 0u; i < sim.drop_count; i = i + 1u) {
+        let drop = sim.drops[i];
+        let distance = length((vec2<f32>(cell) + 0.5 - drop.xy) * sim.cell) / drop.z;
+        // Smooth dent, deepest in the middle and level with the water at the radius
+        dent = dent + drop.w * (0.5 + 0.5 * cos(min(distance, 1.0) * 3.1415927));
+    }
+    // Lowering the height a step ago too starts the dent still
+    next[index(cell)] = height(cell) - dent;
+}
+
+@compute @workgroup_size(8, 8)
+fn step_waves(@builtin(global_invocation_id) id: vec3<u32>) {
+    if any(id.xy >= sim.size) {
+        return;
+    }
+    let cell = vec2<i32>(id.xy);
+    let here = height(cell);
+    let across = height(cell - vec2(1, 0)).x + height(cell + vec2(1, 0)).x - 2.0 * here.x;
+    let down = height(cell - vec2(0, 1)).x + height(cell + vec2(0, 1)).x - 2.0 * here.x;
+    // Leapfrog step of the wave equation, both heights shrink to damp it
+    let after = 2.0 * here.x - here.y + sim.courant.x * across + sim.courant.y * down;
+    next[index(cell)] = vec2(after, here.x) * sim.keep;
+}
@@ -0,0 +1,71 @@
+use std::collections::HashMap;
+
+/// Texture that can be rendered to, sampled and copied from
+pub struct RenderTarget {
+    pub texture: wgpu::Texture,
+    pub view: wgpu::TextureView,
+}
+
+impl RenderTarget {
+    pub fn new(
+        device: &wgpu::Device,
+        width: u32,
+        height: u32,
+        format: wgpu::TextureFormat,
+    ) -> Self {
+        let texture = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("Render target"),
+            size: wgpu::Extent3d {
+                width,
+                height,
+                depth_or_array_layers: 1,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format,
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT
+                | wgpu::TextureUsages::TEXTURE_BINDING
+                | wgpu::TextureUsages::COPY_SRC,
+        });
+        let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+
+        RenderTarget { texture, view }
+    }
+}
+
+/// Keeps render targets alive between frames so they are only allocated when the size or format
+/// changes
+#[derive(Default)]
+pub struct RenderTargetPool {
+    targets: HashMap<(u32, u32, wgpu::TextureFormat), RenderTarget>,
+}
+
+impl RenderTargetPool {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns the target for this size and format, creating it the first time it is asked for
+    ///
+    /// # Example
+    /// ```ignore
+    /// let scene = pool.get(&device, size.width, size.height, config.format);
+    /// ```
+    pub fn get(
+        &mut self,
+        device: &wgpu::Device,
+        width: u32,
+        height: u32,
+        format: wgpu::TextureFormat,
+    ) -> &RenderTarget {
+        self.targets
+            .entry((width, height, format))
+            .or_insert_with(|| RenderTarget::new(device, width, height, format))
+    }
+
+    /// Frees every target, called when the window is resized so old sizes don't pile up
+    pub fn clear(&mut self) {
+        self.targets.clear();
+    }
+}
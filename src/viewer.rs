@@ -0,0 +1,552 @@
+use crate::adapter::{self, AdapterOptions};
+use crate::animation::{Animation, BackgroundSource};
+use crate::clock::Clock;
+use crate::defs;
+use crate::error::WaterError;
+use crate::params::{Horizon, ParamsWatcher, WaterParams};
+use crate::renderer::WaterRenderer;
+use crate::simulation::FALLBACK_RESOLUTION;
+use crate::targets::RenderTargetPool;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use winit::{
+    event::*,
+    event_loop::{ControlFlow, EventLoop},
+    window::{Window, WindowBuilder},
+};
+
+// How long to wait between attempts at recreating a lost device
+const RECOVERY_INTERVAL: Duration = Duration::from_secs(1);
+
+// Everything made with the graphics device, rebuilt from the State when the device is lost
+struct Gpu {
+    surface: wgpu::Surface,
+    config: wgpu::SurfaceConfiguration,
+    device: wgpu::Device,
+    queue: wgpu::Queue,
+    background: defs::Background,
+    water: WaterRenderer,
+    // Scene textures read by the water pass, reused until the window is resized
+    targets: RenderTargetPool,
+    // Set from the device's error handler once it stops working
+    lost: Arc<AtomicBool>,
+}
+
+impl Gpu {
+    /// Creates a device for `window` and uploads the background and parameters to it
+    ///
+    /// # Example
+    /// ```ignore
+    /// let gpu = Gpu::new(&window, &animation, &params, &adapter).await?;
+    /// ```
+    async fn new(
+        window: &Window,
+        animation: &Animation,
+        params: &WaterParams,
+        adapter: &AdapterOptions,
+    ) -> Result<Self, WaterError> {
+        // A minimised window has no size, but the textures still need one
+        let size = window.inner_size();
+        let (width, height) = (size.width.max(1), size.height.max(1));
+
+        let instance = wgpu::Instance::new(adapter.backend.backends());
+        let surface = unsafe { instance.create_surface(window) };
+        let adapter = adapter.select(&instance, Some(&surface)).await?;
+        let (device, queue) = adapter::request_device(&adapter).await?;
+
+        // The default handler panics. A lost device shows up here as errors from whatever call
+        // ran into it, anything else is a mistake that rebuilding the device wouldn't fix
+        let lost = Arc::new(AtomicBool::new(false));
+        let flag = lost.clone();
+        device.on_uncaptured_error(move |error| {
+            log::error!("{}", error);
+            if is_device_lost(&error) {
+                flag.store(true, Ordering::Relaxed);
+            }
+        });
+
+        let format = surface_format(&surface.get_supported_formats(&adapter)).ok_or_else(|| {
+            WaterError::Adapter {
+                adapter: adapter.get_info().name,
+            }
+        })?;
+        let config = wgpu::SurfaceConfiguration {
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT,
+            format,
+            alpha_mode: wgpu::CompositeAlphaMode::Auto,
+            width,
+            height,
+            present_mode: wgpu::PresentMode::Fifo,
+        };
+
+        let background =
+            defs::Background::new(animation, &device, &queue, config.format, params.horizon)?;
+
+        let mut targets = RenderTargetPool::new();
+        // The scene is drawn in the surface format so the water reads it back with the same
+        // encoding it writes
+        let scene = targets.get(&device, width, height, config.format);
+        let mut water = WaterRenderer::new(&device, config.format, &scene.view, *params)?;
+        water.set_horizon(&queue, background.screen_horizon());
+        water.set_size(width, height);
+        if adapter::is_software(&adapter) {
+            water.limit_simulation(FALLBACK_RESOLUTION);
+        }
+        surface.configure(&device, &config);
+
+        Ok(Gpu {
+            surface,
+            config,
+            device,
+            queue,
+            background,
+            water,
+            targets,
+            lost,
+        })
+    }
+
+    fn is_lost(&self) -> bool {
+        self.lost.load(Ordering::Relaxed)
+    }
+
+    fn resize(&mut self, width: u32, height: u32) {
+        self.config.width = width;
+        self.config.height = height;
+        self.surface.configure(&self.device, &self.config);
+        self.targets.clear();
+        let scene = self
+            .targets
+            .get(&self.device, width, height, self.config.format);
+        self.water.set_input(&self.device, &scene.view);
+        self.water.set_size(width, height);
+    }
+
+    fn set_horizon(&mut self, horizon: Horizon) {
+        let screen_horizon = self.background.set_horizon(&self.queue, horizon);
+        self.water.set_horizon(&self.queue, screen_horizon);
+    }
+}
+
+// Structure which contains basic state information for the program
+struct State {
+    // None while the device is being recreated
+    gpu: Option<Gpu>,
+    size: winit::dpi::PhysicalSize<u32>,
+    // Kept so everything on the gpu can be rebuilt without going back to the files
+    animation: Animation,
+    params: WaterParams,
+    source: BackgroundSource,
+    adapter: AdapterOptions,
+    params_watcher: Option<ParamsWatcher>,
+    clock: Clock,
+    // When the device was last recreated, so a failing driver isn't retried every frame
+    last_recovery: Option<Instant>,
+    // Set when the surface was lost and reconfigured, if it is lost again the device is rebuilt
+    surface_lost: bool,
+    cursor: winit::dpi::PhysicalPosition<f64>,
+    // Set while the horizon is being dragged with the right mouse button
+    dragging_horizon: bool,
+    // Where the last ripple was started while the left mouse button is held down
+    last_ripple: Option<winit::dpi::PhysicalPosition<f64>>,
+}
+
+impl State {
+    /// Initialises handler of state based off winit window
+    ///
+    /// # Example
+    /// ```ignore
+    /// let state = State::new(&window, source, params_watcher, adapter).await?;
+    /// ```
+    async fn new(
+        window: &Window,
+        source: BackgroundSource,
+        mut params_watcher: Option<ParamsWatcher>,
+        adapter: AdapterOptions,
+    ) -> Result<Self, WaterError> {
+        // Background texture loading
+        let animation = source.load().map_err(|e| WaterError::Background {
+            path: source.path.clone(),
+            source: e,
+        })?;
+
+        let params = match &mut params_watcher {
+            Some(watcher) => match watcher.poll() {
+                Some(params) => params.map_err(|e| WaterError::Params {
+                    path: watcher.path().to_owned(),
+                    source: e,
+                })?,
+                None => WaterParams::default(),
+            },
+            None => WaterParams::default(),
+        };
+
+        let gpu = Gpu::new(window, &animation, &params, &adapter).await?;
+        Ok(State {
+            gpu: Some(gpu),
+            size: window.inner_size(),
+            animation,
+            params,
+            source,
+            adapter,
+            params_watcher,
+            clock: Clock::real_time(),
+            last_recovery: None,
+            surface_lost: false,
+            cursor: winit::dpi::PhysicalPosition::default(),
+            dragging_horizon: false,
+            last_ripple: None,
+        })
+    }
+
+    /// Updates internal size when window is resized
+    ///
+    /// # Example
+    /// ```ignore
+    /// state.resize(window.inner_size)
+    /// ```
+    fn resize(&mut self, new_size: winit::dpi::PhysicalSize<u32>) {
+        if new_size.height == 0 || new_size.width == 0 {
+            return;
+        }
+
+        self.size = new_size;
+        if let Some(gpu) = &mut self.gpu {
+            gpu.resize(new_size.width, new_size.height);
+        }
+    }
+
+    /// Swaps the background for the image, animation or directory of frames at `path`
+    ///
+    /// # Example
+    /// ```ignore
+    /// state.set_background(Path::new("flag.gif"))?;
+    /// ```
+    fn set_background(&mut self, path: &Path) -> anyhow::Result<()> {
+        let source = BackgroundSource {
+            path: Some(path.to_owned()),
+            ..self.source.clone()
+        };
+        let animation = source.load()?;
+        if let Some(gpu) = &mut self.gpu {
+            gpu.background
+                .set_animation(&gpu.device, &gpu.queue, &animation)?;
+            gpu.water
+                .set_horizon(&gpu.queue, gpu.background.screen_horizon());
+        }
+        self.animation = animation;
+        self.source = source;
+        Ok(())
+    }
+
+    /// Moves the line where the water meets the background
+    fn set_horizon(&mut self, horizon: Horizon) {
+        self.params.horizon = horizon;
+        if let Some(gpu) = &mut self.gpu {
+            gpu.set_horizon(horizon);
+        }
+    }
+
+    /// Handles mouse input, dragging with the right button moves the horizon and clicking or
+    /// dragging with the left starts ripples
+    ///
+    /// Returns true if the event was used
+    fn input(&mut self, event: &WindowEvent) -> bool {
+        match event {
+            WindowEvent::CursorMoved { position, .. } => {
+                self.cursor = *position;
+                if self.dragging_horizon {
+                    self.drag_horizon();
+                }
+                if self.last_ripple.is_some() {
+                    self.drag_ripples();
+                }
+                self.dragging_horizon || self.last_ripple.is_some()
+            }
+            WindowEvent::MouseInput {
+                state,
+                button: MouseButton::Right,
+                ..
+            } => {
+                self.dragging_horizon = *state == ElementState::Pressed;
+                if self.dragging_horizon {
+                    self.drag_horizon();
+                }
+                true
+            }
+            WindowEvent::MouseInput {
+                state,
+                button: MouseButton::Left,
+                ..
+            } => {
+                self.last_ripple = None;
+                if *state == ElementState::Pressed {
+                    self.add_ripple();
+                }
+                true
+            }
+            _ => false,
+        }
+    }
+
+    // Starts a ripple under the cursor
+    fn add_ripple(&mut self) {
+        self.last_ripple = Some(self.cursor);
+        let position = [
+            self.cursor.x as f32 / self.size.width as f32,
+            self.cursor.y as f32 / self.size.height as f32,
+        ];
+        let seconds = self.clock.seconds();
+        if let Some(gpu) = &mut self.gpu {
+            gpu.water.add_ripple(position, seconds);
+        }
+    }
+
+    // Leaves a trail of ripples behind the cursor, a couple of wavelengths apart
+    fn drag_ripples(&mut self) {
+        let Some(last) = self.last_ripple else {
+            return;
+        };
+        let (dx, dy) = (self.cursor.x - last.x, self.cursor.y - last.y);
+        let moved = (dx * dx + dy * dy).sqrt() as f32 / self.size.height as f32;
+        if moved >= 2.0 * self.params.ripples.wavelength {
+            self.add_ripple();
+        }
+    }
+
+    fn drag_horizon(&mut self) {
+        let fraction = self.cursor.y as f32 / self.size.height as f32;
+        self.set_horizon(Horizon::Fraction(fraction));
+    }
+
+    /// Picks up any changes made to the parameters file since the last frame
+    fn reload_params(&mut self) {
+        let Some(params) = self.params_watcher.as_mut().and_then(ParamsWatcher::poll) else {
+            return;
+        };
+        match params {
+            Ok(params) => {
+                if let Some(gpu) = &mut self.gpu {
+                    if let Err(e) = gpu.water.set_params(&gpu.device, params) {
+                        log::error!("{}", e);
+                        return;
+                    }
+                }
+                self.params = params;
+                self.set_horizon(params.horizon);
+            }
+            // Keep the current look until the file is fixed
+            Err(e) => log::error!("{:#}", e),
+        }
+    }
+
+    /// Called when the surface is lost. Reconfiguring it is usually enough, but if it is lost
+    /// again straight away the device is rebuilt
+    fn surface_lost(&mut self, window: &Window) {
+        if self.surface_lost {
+            log::warn!("Surface lost again, recreating the device");
+            self.device_lost(window);
+            return;
+        }
+
+        log::warn!("Loss of surface");
+        self.surface_lost = true;
+        self.resize(self.size);
+    }
+
+    /// Drops the device and everything made with it, then builds them again from the saved
+    /// background and parameters
+    ///
+    /// The clock isn't touched, so the animation carries on from where it was. Ripples and the
+    /// simulated water are dropped with the old renderer, so the water is calm again afterwards.
+    /// If the device can't be made yet it is tried again a second later.
+    fn device_lost(&mut self, window: &Window) {
+        // The old surface has to be gone before another can be made for the window
+        self.gpu = None;
+        if self
+            .last_recovery
+            .is_some_and(|last| last.elapsed() < RECOVERY_INTERVAL)
+        {
+            return;
+        }
+
+        self.last_recovery = Some(Instant::now());
+        match pollster::block_on(Gpu::new(
+            window,
+            &self.animation,
+            &self.params,
+            &self.adapter,
+        )) {
+            Ok(gpu) => {
+                log::info!("Recreated the graphics device");
+                self.gpu = Some(gpu);
+                self.surface_lost = false;
+            }
+            Err(e) => log::error!(
+                "Failed to recreate the graphics device: {:#}",
+                anyhow::Error::new(e)
+            ),
+        }
+    }
+
+    /// Render logic for States subcomponents
+    /// Currently draws water effect for the supplied texture
+    ///
+    /// Rebuilds the device instead of drawing if it has been lost.
+    ///
+    /// # Example
+    /// ```ignore
+    /// state.render(&window);
+    /// ```
+    fn render(&mut self, window: &Window) -> Result<(), WaterError> {
+        self.reload_params();
+        let seconds = self.clock.seconds();
+
+        let gpu = match &mut self.gpu {
+            Some(gpu) if !gpu.is_lost() => gpu,
+            _ => {
+                self.device_lost(window);
+                return Ok(());
+            }
+        };
+
+        // Create output texture for rendering
+        let output = gpu.surface.get_current_texture()?;
+        self.surface_lost = false;
+
+        // Texture that can be read to and wrote from:
+        //  Allows for water shader to read from rendered output so it would reflect moving
+        //  characters
+        let scene = gpu.targets.get(
+            &gpu.device,
+            gpu.config.width,
+            gpu.config.height,
+            gpu.config.format,
+        );
+
+        // Creates view of output to be rendered to
+        let view = output
+            .texture
+            .create_view(&wgpu::TextureViewDescriptor::default());
+
+        // Every pass of the frame is recorded here and submitted together
+        let mut encoder = gpu
+            .device
+            .create_command_encoder(&wgpu::CommandEncoderDescriptor {
+                label: Some("Frame encoder"),
+            });
+
+        // Draws background to both the screen texture and the texture to be read by gpu.water
+        gpu.background.draw(&mut encoder, &scene.view, seconds);
+        gpu.background.draw(&mut encoder, &view, seconds);
+
+        gpu.water
+            .encode(&gpu.device, &gpu.queue, &mut encoder, &view, seconds);
+
+        gpu.queue.submit(std::iter::once(encoder.finish()));
+
+        // Draws contents of output texture to screen
+        output.present();
+
+        Ok(())
+    }
+}
+
+// Running out of memory or losing the device both need a new one. wgpu reports a lost device as
+// a validation error, only told apart from the others by its message
+fn is_device_lost(error: &wgpu::Error) -> bool {
+    match error {
+        wgpu::Error::OutOfMemory { .. } => true,
+        wgpu::Error::Validation { source, .. } => {
+            let source: &(dyn std::error::Error + 'static) = source.as_ref();
+            std::iter::successors(Some(source), |error| error.source())
+                .any(|error| error.to_string().contains("device is lost"))
+        }
+    }
+}
+
+/// Picks an sRGB format if the surface has one, so colours are encoded by the hardware rather than
+/// in the shader
+///
+/// Returns `None` when the surface supports no formats, as when the adapter can't present to it.
+fn surface_format(formats: &[wgpu::TextureFormat]) -> Option<wgpu::TextureFormat> {
+    formats
+        .iter()
+        .copied()
+        .find(|format| format.describe().srgb)
+        .or_else(|| formats.first().copied())
+}
+
+/// Event loop for water shader program
+///
+/// Images, animations or directories of frames dropped onto the window replace the background
+/// while running. Water parameters are read from `params`, if given, and reloaded whenever the
+/// file changes.
+///
+/// A lost surface or device is recreated without stopping the animation, so the window can be
+/// left running indefinitely. Recreating the device calms any ripples and simulated waves.
+///
+/// Returns an error if the window can't be set up, e.g. when no adapter can draw to it or the
+/// background fails to load.
+pub async fn run(
+    source: BackgroundSource,
+    params: Option<PathBuf>,
+    adapter: AdapterOptions,
+) -> Result<(), WaterError> {
+    // Without a logger wgpu errors are not useful
+    crate::init_logging();
+    // Winit initilisation
+    let event_loop = EventLoop::new();
+    let window = WindowBuilder::new().build(&event_loop)?;
+    // Asyncronous builder for the state struct
+    let mut state = State::new(&window, source, params.map(ParamsWatcher::new), adapter).await?;
+
+    event_loop.run(move |event, _, control_flow| match event {
+        Event::WindowEvent {
+            ref event,
+            window_id,
+        } if window_id == window.id() && !state.input(event) => {
+            match event {
+                // Nessacary to
+                WindowEvent::CloseRequested => *control_flow = ControlFlow::Exit,
+
+                WindowEvent::Resized(physical_size) => state.resize(*physical_size),
+                WindowEvent::ScaleFactorChanged { new_inner_size, .. } => {
+                    state.resize(**new_inner_size)
+                }
+
+                WindowEvent::DroppedFile(path) => {
+                    if let Err(e) = state.set_background(path) {
+                        log::error!("{:#}", e);
+                    }
+                }
+
+                _ => {}
+            }
+        }
+
+        Event::MainEventsCleared => {
+            window.request_redraw();
+        }
+
+        Event::RedrawRequested(window_id) if window_id == window.id() => {
+            match state.render(&window) {
+                Ok(_) => {}
+                // Reconfigure lost surface
+                Err(WaterError::Surface(wgpu::SurfaceError::Lost)) => state.surface_lost(&window),
+                // Start again on a fresh device
+                Err(WaterError::Surface(wgpu::SurfaceError::OutOfMemory)) => {
+                    log::warn!("Out of memory, recreating the device");
+                    state.device_lost(&window);
+                }
+
+                // Other errors will be fixed in next frame
+                Err(e) => log::error!("{:#}", anyhow::Error::new(e)),
+            }
+        }
+
+        _ => {}
+    });
+}
@@ -0,0 +1,56 @@
+//! Setup shared by the tests rendering on the software adapter
+
+// Each test binary only uses some of these
+#![allow(dead_code)]
+
+use image::{DynamicImage, Rgba, RgbaImage};
+use wgpu_assignment::{AdapterOptions, Animation, Headless, WaterParams};
+
+/// Width and height of the frames rendered from [`coordinates`]
+pub const SIZE: u32 = 256;
+pub const HORIZON: f32 = 0.5;
+/// Sample positions are read back through 8 bit colours and texture filtering
+pub const TOLERANCE: f32 = 2.5 / SIZE as f32;
+
+/// Red and green count up with x and y, so the colour of a pixel tells where it was sampled from
+pub fn coordinates() -> Animation {
+    Animation::still(DynamicImage::ImageRgba8(RgbaImage::from_fn(
+        SIZE,
+        SIZE,
+        |x, y| Rgba([x as u8, y as u8, 0, 255]),
+    )))
+}
+
+/// Renders `animation` on the software adapter, so the results don't depend on the gpu
+pub fn software_renderer(
+    animation: &Animation,
+    params: WaterParams,
+    width: u32,
+    height: u32,
+) -> Headless {
+    let adapter = AdapterOptions {
+        software: true,
+        ..AdapterOptions::default()
+    };
+    pollster::block_on(Headless::new(animation, params, width, height, &adapter))
+        .expect("These tests need a software adapter such as llvmpipe or lavapipe")
+}
+
+/// Centre of pixel `px`, `py` of a frame of [`SIZE`] as a fraction of the screen
+pub fn screen(px: u32, py: u32) -> [f32; 2] {
+    [
+        (px as f32 + 0.5) / SIZE as f32,
+        (py as f32 + 0.5) / SIZE as f32,
+    ]
+}
+
+/// Water texture coordinates the pixel at `px`, `py` sampled [`coordinates`] from, with the
+/// horizon at [`HORIZON`]
+pub fn sampled(frame: &RgbaImage, px: u32, py: u32) -> [f32; 2] {
+    // The background is squeezed above the horizon
+    let [r, g, _, _] = frame.get_pixel(px, py).0;
+    [
+        (r as f32 + 0.5) / SIZE as f32,
+        (g as f32 + 0.5) / SIZE as f32 * HORIZON,
+    ]
+}
@@ -0,0 +1,320 @@
+//! Renders fixed scenes on the software adapter and compares them with the reference images in
+//! `tests/golden`
+//!
+//! Run with `UPDATE_GOLDEN=1` to write new references after an intended change to the output.
+//! Failed comparisons write a diff image to `target/golden-diff`, with mismatched pixels in red.
+
+mod common;
+
+use common::software_renderer;
+use image::{DynamicImage, Rgba, RgbaImage};
+use std::path::{Path, PathBuf};
+use wgpu_assignment::{
+    Animation, CpuRenderer, Frame, Headless, Horizon, NoiseHash, NoiseKind, NoiseParams, Playback,
+    WaterParams, Wind,
+};
+
+const WIDTH: u32 = 160;
+const HEIGHT: u32 = 120;
+
+// Largest YIQ difference between two colours, from pixelmatch
+const MAX_YIQ_DELTA: f32 = 35215.0;
+// Pixels further apart than this fraction of the largest difference count as mismatched. Small
+// enough to catch a visible change, large enough to ignore rounding between drivers
+const PIXEL_THRESHOLD: f32 = 0.1;
+// Fraction of the pixels allowed to mismatch, for edges rasterised slightly differently
+const MISMATCH_TOLERANCE: f32 = 0.005;
+
+struct Scene {
+    name: &'static str,
+    animation: fn() -> Animation,
+    params: WaterParams,
+    seconds: f32,
+}
+
+fn scenes() -> Vec<Scene> {
+    vec![
+        Scene {
+            name: "still_default",
+            animation: still,
+            params: WaterParams::default(),
+            seconds: 0.0,
+        },
+        Scene {
+            name: "still_later",
+            animation: still,
+            params: WaterParams::default(),
+            seconds: 2.5,
+        },
+        Scene {
+            name: "source_row_rough",
+            animation: still,
+            params: WaterParams {
+                amplitude: 0.04,
+                noise_scale: 8.0,
+                horizon: Horizon::SourceRow(40),
+                ..WaterParams::default()
+            },
+            seconds: 1.0,
+        },
+        Scene {
+            name: "animated_second_frame",
+            animation: animated,
+            params: WaterParams {
+                horizon: Horizon::Fraction(0.6),
+                ..WaterParams::default()
+            },
+            seconds: 0.15,
+        },
+        Scene {
+            name: "choppy_warped_simplex",
+            animation: still,
+            params: WaterParams {
+                amplitude: 0.03,
+                noise_scale: 4.0,
+                noise: NoiseParams {
+                    kind: NoiseKind::Simplex,
+                    octaves: 5,
+                    warp: 0.6,
+                    ..NoiseParams::default()
+                },
+                ..WaterParams::default()
+            },
+            seconds: 0.7,
+        },
+        Scene {
+            name: "worley_fbm",
+            animation: still,
+            params: WaterParams {
+                amplitude: 0.03,
+                noise_scale: 3.0,
+                noise: NoiseParams {
+                    kind: NoiseKind::Worley,
+                    octaves: 3,
+                    ..NoiseParams::default()
+                },
+                ..WaterParams::default()
+            },
+            seconds: 1.2,
+        },
+        Scene {
+            name: "pcg_after_a_day",
+            animation: still,
+            params: WaterParams {
+                amplitude: 0.02,
+                noise_scale: 3.0,
+                noise: NoiseParams {
+                    hash: NoiseHash::Pcg,
+                    octaves: 3,
+                    ..NoiseParams::default()
+                },
+                ..WaterParams::default()
+            },
+            seconds: 86400.3,
+        },
+        Scene {
+            name: "windy_lake",
+            animation: still,
+            params: WaterParams {
+                amplitude: 0.03,
+                noise_scale: 6.0,
+                wind: Some(Wind {
+                    direction: 20.0,
+                    anisotropy: 4.0,
+                    ..Wind::default()
+                }),
+                ..WaterParams::default()
+            },
+            seconds: 3.0,
+        },
+    ]
+}
+
+// Colour bands with a grid, so both the distortion and the reflection show up in the output
+fn pattern(width: u32, height: u32, tint: [u8; 3]) -> DynamicImage {
+    DynamicImage::ImageRgba8(RgbaImage::from_fn(width, height, |x, y| {
+        if x % 16 == 0 || y % 16 == 0 {
+            return Rgba([255, 255, 255, 255]);
+        }
+        let band = (y * 4 / height) as u8;
+        Rgba([
+            tint[0].saturating_add(band * 40),
+            tint[1].saturating_add((x * 255 / width) as u8 / 2),
+            tint[2].saturating_sub(band * 30),
+            255,
+        ])
+    }))
+}
+
+fn still() -> Animation {
+    Animation::still(pattern(80, 60, [40, 60, 200]))
+}
+
+fn animated() -> Animation {
+    let frame = |tint| Frame {
+        image: pattern(80, 60, tint),
+        delay: 0.1,
+    };
+    Animation {
+        frames: vec![frame([40, 60, 200]), frame([200, 80, 40])],
+        playback: Playback::Loop,
+    }
+}
+
+fn yiq(pixel: &Rgba<u8>) -> [f32; 3] {
+    let [r, g, b, _] = pixel.0.map(f32::from);
+    [
+        0.298_895_3 * r + 0.586_622_5 * g + 0.114_482_2 * b,
+        0.595_977_99 * r - 0.274_176_1 * g - 0.321_801_9 * b,
+        0.211_470_17 * r - 0.522_617_1 * g + 0.311_146_94 * b,
+    ]
+}
+
+// Perceptual colour difference from "Measuring perceived color difference using YIQ NTSC
+// transmission color space in mobile applications", as used by pixelmatch
+fn delta(a: &Rgba<u8>, b: &Rgba<u8>) -> f32 {
+    let (a, b) = (yiq(a), yiq(b));
+    let (y, i, q) = (a[0] - b[0], a[1] - b[1], a[2] - b[2]);
+    0.5053 * y * y + 0.299 * i * i + 0.1957 * q * q
+}
+
+/// Returns the fraction of mismatched pixels and an image marking them
+fn compare(actual: &RgbaImage, expected: &RgbaImage) -> (f32, RgbaImage) {
+    let limit = MAX_YIQ_DELTA * PIXEL_THRESHOLD * PIXEL_THRESHOLD;
+    let mut mismatched = 0;
+    let diff = RgbaImage::from_fn(actual.width(), actual.height(), |x, y| {
+        let (a, e) = (actual.get_pixel(x, y), expected.get_pixel(x, y));
+        if delta(a, e) > limit {
+            mismatched += 1;
+            Rgba([255, 0, 0, 255])
+        } else {
+            // Faded copy of the expected image, so the red stands out
+            let grey = (yiq(e)[0] / 4.0 + 191.0) as u8;
+            Rgba([grey, grey, grey, 255])
+        }
+    });
+    let pixels = (actual.width() * actual.height()) as f32;
+    (mismatched as f32 / pixels, diff)
+}
+
+fn golden_dir() -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/golden")
+}
+
+fn diff_dir() -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR")).join("target/golden-diff")
+}
+
+/// Checks `actual` against the reference called `name`, returning why it doesn't match
+fn check(name: &str, actual: &RgbaImage) -> Result<(), String> {
+    let path = golden_dir().join(format!("{}.png", name));
+    if std::env::var_os("UPDATE_GOLDEN").is_some() {
+        std::fs::create_dir_all(golden_dir()).unwrap();
+        actual.save(&path).unwrap();
+        return Ok(());
+    }
+
+    let expected = match image::open(&path) {
+        Ok(image) => image.to_rgba8(),
+        Err(e) => {
+            return Err(format!(
+                "{}: can't read {} ({}), run with UPDATE_GOLDEN=1 to create it",
+                name,
+                path.display(),
+                e
+            ))
+        }
+    };
+    if expected.dimensions() != actual.dimensions() {
+        return Err(format!(
+            "{}: rendered {:?} but the reference is {:?}",
+            name,
+            actual.dimensions(),
+            expected.dimensions()
+        ));
+    }
+
+    let (mismatch, diff) = compare(actual, &expected);
+    if mismatch <= MISMATCH_TOLERANCE {
+        return Ok(());
+    }
+
+    std::fs::create_dir_all(diff_dir()).unwrap();
+    let diff_path = diff_dir().join(format!("{}_diff.png", name));
+    let actual_path = diff_dir().join(format!("{}_actual.png", name));
+    diff.save(&diff_path).unwrap();
+    actual.save(&actual_path).unwrap();
+    Err(format!(
+        "{}: {:.2}% of pixels differ, see {} and {}",
+        name,
+        mismatch * 100.0,
+        diff_path.display(),
+        actual_path.display()
+    ))
+}
+
+#[test]
+fn golden_images() {
+    // All scenes share one device, creating several at once is slow on software adapters
+    let mut headless: Option<Headless> = None;
+    let mut failures = Vec::new();
+    for scene in scenes() {
+        let animation = (scene.animation)();
+        let renderer = match &mut headless {
+            Some(renderer) => {
+                renderer.set_background(&animation).unwrap();
+                renderer
+            }
+            None => headless.insert(software_renderer(&animation, scene.params, WIDTH, HEIGHT)),
+        };
+        renderer.set_params(scene.params).unwrap();
+
+        let frame = renderer.render(scene.seconds).unwrap();
+        if let Err(failure) = check(scene.name, &frame) {
+            failures.push(failure);
+        }
+    }
+
+    assert!(failures.is_empty(), "\n{}", failures.join("\n"));
+}
+
+// The references come from the gpu, so this also checks the cpu renderer matches it
+#[test]
+fn cpu_renderer_matches_golden_images() {
+    if std::env::var_os("UPDATE_GOLDEN").is_some() {
+        return;
+    }
+
+    let mut failures = Vec::new();
+    for scene in scenes() {
+        let animation = (scene.animation)();
+        let mut renderer = CpuRenderer::new(&animation, scene.params, WIDTH, HEIGHT).unwrap();
+        let frame = renderer.render(scene.seconds);
+        if let Err(failure) = check(scene.name, &frame) {
+            failures.push(format!("cpu {}", failure));
+        }
+    }
+
+    assert!(failures.is_empty(), "\n{}", failures.join("\n"));
+}
+
+#[test]
+fn identical_images_match() {
+    let image = pattern(32, 32, [10, 20, 30]).to_rgba8();
+    assert_eq!(compare(&image, &image).0, 0.0);
+}
+
+#[test]
+fn changed_pixels_are_counted() {
+    let expected = pattern(10, 10, [10, 20, 30]).to_rgba8();
+    let mut actual = expected.clone();
+    actual.put_pixel(3, 3, Rgba([255, 0, 255, 255]));
+    // Barely visible changes are tolerated
+    let faint = actual.get_pixel(5, 5).0.map(|c| c.saturating_add(1));
+    actual.put_pixel(5, 5, Rgba(faint));
+
+    let (mismatch, diff) = compare(&actual, &expected);
+    assert_eq!(mismatch, 0.01);
+    assert_eq!(diff.get_pixel(3, 3), &Rgba([255, 0, 0, 255]));
+    assert_ne!(diff.get_pixel(5, 5), &Rgba([255, 0, 0, 255]));
+}
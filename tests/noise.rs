@@ -0,0 +1,155 @@
+//! Checks the CPU copy of the water distortion in `wgpu_assignment::noise` against what the
+//! shader renders on the software adapter
+
+mod common;
+
+use common::{coordinates, sampled, screen, software_renderer, HORIZON, SIZE, TOLERANCE};
+use image::RgbaImage;
+use wgpu_assignment::noise::water_sample_coords;
+use wgpu_assignment::{
+    Horizon, NoiseHash, NoiseKind, NoiseParams, RippleParams, Ripples, WaterParams, Wind,
+};
+
+// Largest distance between where the water in `frame` sampled the background and where
+// `expected` says it should have, for a grid of points on the water
+fn worst_error(frame: &RgbaImage, expected: impl Fn([f32; 2]) -> [f32; 2]) -> f32 {
+    let mut worst = 0.0f32;
+    for py in (SIZE / 2..SIZE).step_by(7) {
+        for px in (0..SIZE).step_by(5) {
+            // Screen pixel to water texture coordinates, mirrored about the horizon
+            let screen = screen(px, py);
+            let expected = expected([screen[0], 2.0 * HORIZON - screen[1]]);
+
+            let actual = sampled(frame, px, py);
+            let error = (actual[0] - expected[0])
+                .abs()
+                .max((actual[1] - expected[1]).abs());
+            worst = worst.max(error);
+        }
+    }
+    worst
+}
+
+#[test]
+fn gpu_samples_where_the_cpu_says() {
+    let params = WaterParams {
+        amplitude: 0.05,
+        noise_scale: 6.0,
+        horizon: Horizon::Fraction(HORIZON),
+        ..WaterParams::default()
+    };
+    let mut headless = software_renderer(&coordinates(), params, SIZE, SIZE);
+
+    let fbm = NoiseParams {
+        octaves: 4,
+        ..NoiseParams::default()
+    };
+    let noises = [
+        NoiseParams::default(),
+        fbm,
+        NoiseParams {
+            kind: NoiseKind::Simplex,
+            ..fbm
+        },
+        NoiseParams {
+            kind: NoiseKind::Worley,
+            ..NoiseParams::default()
+        },
+        NoiseParams { warp: 0.8, ..fbm },
+        NoiseParams {
+            hash: NoiseHash::Pcg,
+            ..fbm
+        },
+        NoiseParams {
+            kind: NoiseKind::Worley,
+            hash: NoiseHash::Pcg,
+            ..NoiseParams::default()
+        },
+    ];
+    // The last time is a day in, which only the pcg hash is expected to survive
+    let times = |noise: NoiseParams| match noise.hash {
+        NoiseHash::Sine => vec![(noise, 0.0), (noise, 1.7)],
+        NoiseHash::Pcg => vec![(noise, 0.0), (noise, 1.7), (noise, 86400.3)],
+    };
+    let winds = [
+        Wind::default(),
+        Wind {
+            direction: 250.0,
+            speed: 2.0,
+            anisotropy: 0.5,
+            gust_strength: 1.0,
+            gust_period: 0.8,
+        },
+    ];
+    let cases = noises
+        .into_iter()
+        .flat_map(times)
+        .map(|(noise, seconds)| (noise, None, seconds))
+        .chain(winds.into_iter().flat_map(|wind| {
+            [0.0, 1.7].map(|seconds| (NoiseParams::default(), Some(wind), seconds))
+        }));
+    for (noise, wind, seconds) in cases {
+        let params = WaterParams {
+            noise,
+            wind,
+            ..params
+        };
+        headless.set_params(params).unwrap();
+        let frame = headless.render(seconds).unwrap();
+        let worst = worst_error(&frame, |tex_coords| {
+            water_sample_coords(&params, HORIZON, tex_coords, seconds)
+        });
+        assert!(
+            worst <= TOLERANCE,
+            "With {:?} and {:?} at {}s the GPU sampled {} texture coordinates away from the CPU",
+            noise,
+            wind,
+            seconds,
+            worst
+        );
+    }
+}
+
+#[test]
+fn gpu_ripples_match_the_cpu() {
+    // No noise, so only the ripples move the water
+    let params = WaterParams {
+        amplitude: 0.0,
+        bias: 0.0,
+        horizon: Horizon::Fraction(HORIZON),
+        ripples: RippleParams {
+            strength: 0.03,
+            ..RippleParams::default()
+        },
+        ..WaterParams::default()
+    };
+    let mut headless = software_renderer(&coordinates(), params, SIZE, SIZE);
+
+    let mut ripples = Ripples::new();
+    for (position, start) in [([0.3, 0.7], 0.0), ([0.6, 0.8], 0.4), ([0.9, 0.55], 0.9)] {
+        assert!(headless.add_ripple(position, start));
+        let centre = [position[0], 2.0 * HORIZON - position[1]];
+        ripples.add(&params.ripples, 1.0, centre, start);
+    }
+    assert!(
+        !headless.add_ripple([0.5, 0.2], 0.0),
+        "Ripples start above the water"
+    );
+
+    for seconds in [0.5, 1.2] {
+        let frame = headless.render(seconds).unwrap();
+        let worst = worst_error(&frame, |tex_coords| {
+            let offset = ripples.offset(&params.ripples, 1.0, tex_coords, seconds);
+            [
+                (tex_coords[0] + offset[0]).clamp(0.0, 1.0),
+                (tex_coords[1] + offset[1]).clamp(0.0, HORIZON),
+            ]
+        });
+        assert!(
+            worst <= TOLERANCE,
+            "At {}s the GPU sampled {} texture coordinates away from the CPU",
+            seconds,
+            worst
+        );
+    }
+}
@@ -0,0 +1,133 @@
+//! Runs the heightfield simulation on the software adapter and checks how disturbances move
+//! through the rendered water
+
+mod common;
+
+use common::{coordinates, sampled, screen, software_renderer, HORIZON, SIZE, TOLERANCE};
+use image::RgbaImage;
+use wgpu_assignment::{Headless, Horizon, SimulationParams, WaterParams};
+
+// Where the stone lands, as a fraction of the screen
+const DROP: [f32; 2] = [0.5, 0.75];
+
+// Only the simulation moves the water, strongly enough for its waves to stand out from rounding
+fn renderer(simulation: SimulationParams) -> Headless {
+    let simulation = SimulationParams {
+        strength: 0.15,
+        ..simulation
+    };
+    let params = WaterParams {
+        amplitude: 0.0,
+        bias: 0.0,
+        horizon: Horizon::Fraction(HORIZON),
+        simulation: Some(simulation),
+        ..WaterParams::default()
+    };
+    software_renderer(&coordinates(), params, SIZE, SIZE)
+}
+
+// Renders frames every tenth of a second from `start` up to `end`, returning the last
+fn run(headless: &mut Headless, start: f32, end: f32) -> RgbaImage {
+    let mut seconds = start;
+    loop {
+        let frame = headless.render(seconds).unwrap();
+        if seconds >= end {
+            return frame;
+        }
+        seconds = (seconds + 0.1).min(end);
+    }
+}
+
+// How far the water moved the reflection at each pixel below the horizon, with the pixel's
+// distance from the drop in screen heights
+fn displacements(frame: &RgbaImage) -> Vec<(f32, f32)> {
+    let mut displacements = Vec::new();
+    for py in (SIZE / 2..SIZE).step_by(3) {
+        for px in (0..SIZE).step_by(3) {
+            let screen = screen(px, py);
+            let still = [screen[0], 2.0 * HORIZON - screen[1]];
+            let sampled = sampled(frame, px, py);
+            let moved = (sampled[0] - still[0])
+                .abs()
+                .max((sampled[1] - still[1]).abs());
+            let distance = (screen[0] - DROP[0]).hypot(screen[1] - DROP[1]);
+            displacements.push((distance, moved));
+        }
+    }
+    displacements
+}
+
+// Largest displacement between `near` and `far` screen heights from the drop
+fn largest(displacements: &[(f32, f32)], near: f32, far: f32) -> f32 {
+    displacements
+        .iter()
+        .filter(|(distance, _)| (near..far).contains(distance))
+        .map(|&(_, moved)| moved)
+        .fold(0.0, f32::max)
+}
+
+#[test]
+fn drops_spread_out_in_rings() {
+    let params = SimulationParams::default();
+    let mut headless = renderer(params);
+    assert!(headless.add_ripple(DROP, 0.0));
+    assert!(
+        !headless.add_ripple([0.5, 0.2], 0.0),
+        "Drops land on the water"
+    );
+
+    let start = displacements(&headless.render(0.0).unwrap());
+    assert!(largest(&start, 0.0, params.drop_size) > 4.0 * TOLERANCE);
+    assert!(largest(&start, 2.0 * params.drop_size, 2.0) <= TOLERANCE);
+
+    // The front of the ring has moved out, leaving the middle to settle and the rest untouched
+    let seconds = 0.6;
+    let front = params.speed * seconds;
+    let later = displacements(&run(&mut headless, 0.1, seconds));
+    let ring = largest(&later, front - 0.05, front + 0.02);
+    assert!(ring > 2.0 * TOLERANCE, "the ring moved the water {}", ring);
+    assert!(largest(&later, 0.0, front / 3.0) < ring / 2.0);
+    assert!(largest(&later, front + 0.08, 2.0) <= TOLERANCE);
+}
+
+#[test]
+fn waves_reflect_off_the_edges() {
+    // Without damping the waves only leave if the edges let them
+    let params = SimulationParams {
+        damping: 0.0,
+        ..SimulationParams::default()
+    };
+    let mut headless = renderer(params);
+    headless.add_ripple(DROP, 0.0);
+
+    // Long enough for the first ring to have crossed the whole screen, and a few steps with
+    // nothing to show an unstable simulation blowing up
+    let frame = displacements(&run(&mut headless, 0.0, 4.0));
+    let largest = largest(&frame, 0.0, 2.0);
+    assert!(largest > TOLERANCE, "the water is flat");
+    assert!(largest < 0.1, "the water moved {}", largest);
+}
+
+#[test]
+fn waves_die_away() {
+    let params = SimulationParams {
+        damping: 2.0,
+        ..SimulationParams::default()
+    };
+    let mut headless = renderer(params);
+    headless.add_ripple(DROP, 0.0);
+
+    let frame = displacements(&run(&mut headless, 0.0, 4.0));
+    assert!(largest(&frame, 0.0, 2.0) <= TOLERANCE);
+}
+
+#[test]
+fn going_back_in_time_calms_the_water() {
+    let mut headless = renderer(SimulationParams::default());
+    headless.add_ripple(DROP, 1.0);
+    let disturbed = displacements(&headless.render(1.0).unwrap());
+    assert!(largest(&disturbed, 0.0, 2.0) > 4.0 * TOLERANCE);
+
+    let restarted = displacements(&headless.render(0.5).unwrap());
+    assert!(largest(&restarted, 0.0, 2.0) <= TOLERANCE);
+}